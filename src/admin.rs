@@ -0,0 +1,173 @@
+use anyhow::bail;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::time::timeout;
+
+use crate::metrics;
+use crate::proxy::{ProxyBufferedRead, ProxyListener, ProxyWriteHalf};
+use crate::PodmanSocketConnector;
+
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+const PING_REQUEST: &[u8] = b"GET /_ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+/// Open the admin listener, on a TCP address or else on a Unix socket path
+///
+/// An existing socket at the Unix socket path is replaced, other files are not.
+pub async fn open_listener(address: &str) -> std::io::Result<(ProxyListener, Option<PathBuf>)> {
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return Ok((TcpListener::bind(address).await?.into(), None));
+    }
+
+    // Only a previous socket is replaced, e.g. not a file given by mistake
+    if let Ok(metadata) = tokio::fs::symlink_metadata(address).await {
+        if !metadata.file_type().is_socket() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", address),
+            ));
+        }
+        tokio::fs::remove_file(address).await?;
+    }
+    Ok((
+        UnixListener::bind(address)?.into(),
+        Some(PathBuf::from(address)),
+    ))
+}
+
+/// Serve the metrics and the health endpoints, apart from the filtered proxy socket
+pub async fn serve(listener: ProxyListener, podman_connector: Arc<PodmanSocketConnector>) {
+    loop {
+        match listener.accept().await {
+            Ok(accepted) => {
+                let podman_connector = podman_connector.clone();
+
+                tokio::spawn(async move {
+                    let (reader, writer) = match accepted.establish().await {
+                        Ok(stream) => stream.split(),
+                        Err(e) => {
+                            log::debug!("Error accepting an admin client: {}", e);
+                            return;
+                        }
+                    };
+                    if let Err(e) = handle_admin(reader, writer, &podman_connector).await {
+                        log::debug!("Error serving an admin request: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                log::error!("Error accepting an admin client: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+/// Build a complete response closing the connection
+fn response(status: &str, content_type: &str, body: &[u8]) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+fn json_response(status: &str, body: serde_json::Value) -> Vec<u8> {
+    response(status, "application/json", body.to_string().as_bytes())
+}
+
+/// Check that Podman answers `/_ping` with a 200
+async fn ping_podman(podman_connector: &PodmanSocketConnector) -> anyhow::Result<()> {
+    let mut podman_sock = podman_connector.connect().await?;
+    podman_sock.write_all(PING_REQUEST).await?;
+
+    let mut buffer = Vec::with_capacity(1024);
+    loop {
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut res = httparse::Response::new(&mut headers);
+
+        if let httparse::Status::Complete(_) = res.parse(&buffer)? {
+            return match res.code {
+                Some(200) => Ok(()),
+                code => bail!("/_ping answered with status {}", code.unwrap_or_default()),
+            };
+        }
+        if buffer.len() >= MAX_REQUEST_SIZE {
+            bail!("/_ping response too large");
+        }
+        if podman_sock.read_buf(&mut buffer).await? == 0 {
+            bail!("connection closed before the /_ping response");
+        }
+    }
+}
+
+/// Answer a single request
+///
+/// - `/metrics` with the Prometheus metrics
+/// - `/healthz` with a 200 while the proxy runs
+/// - `/readyz` with a 200 if Podman answers `/_ping`, a 503 otherwise
+async fn handle_admin(
+    mut reader: ProxyBufferedRead,
+    mut writer: ProxyWriteHalf,
+    podman_connector: &PodmanSocketConnector,
+) -> anyhow::Result<()> {
+    let mut buffer = Vec::with_capacity(1024);
+
+    let (method, target) = loop {
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut req = httparse::Request::new(&mut headers);
+
+        if let httparse::Status::Complete(_) = req.parse(&buffer)? {
+            break (
+                req.method.map(String::from).unwrap_or_default(),
+                req.path.map(String::from).unwrap_or_default(),
+            );
+        }
+        if buffer.len() >= MAX_REQUEST_SIZE {
+            bail!("request too large");
+        }
+        if timeout(READ_TIMEOUT, reader.read(&mut buffer)).await?? == 0 {
+            return Ok(());
+        }
+    };
+
+    let path = target.split('?').next().unwrap_or_default();
+    let response = match (method.as_str(), path) {
+        ("GET", "/metrics") => {
+            let (content_type, body) = metrics::encode()?;
+            response("200 OK", &content_type, &body)
+        }
+        ("GET", "/healthz") => json_response("200 OK", json!({ "status": "ok" })),
+        ("GET", "/readyz") => match timeout(PING_TIMEOUT, ping_podman(podman_connector)).await {
+            Ok(Ok(())) => json_response("200 OK", json!({ "status": "ok" })),
+            Ok(Err(e)) => json_response(
+                "503 Service Unavailable",
+                json!({ "status": "unavailable", "error": format!("{:#}", e) }),
+            ),
+            Err(_) => json_response(
+                "503 Service Unavailable",
+                json!({ "status": "unavailable", "error": "Podman did not answer /_ping in time" }),
+            ),
+        },
+        (_, "/metrics" | "/healthz" | "/readyz") => response(
+            "405 Method Not Allowed",
+            "text/plain; charset=utf-8",
+            b"method not allowed",
+        ),
+        _ => response("404 Not Found", "text/plain; charset=utf-8", b"not found"),
+    };
+
+    writer.write(&response).await?;
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use serde::Serialize;
+use std::time::{Instant, SystemTime};
+use tokio::fs::OpenOptions;
+use tokio::io::{self, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::metrics;
+use crate::proxy::ClientIdentity;
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Allowed,
+    Denied,
+    /// Forwarded, but denied by a decision in audit mode
+    #[serde(rename = "would_deny")]
+    WouldDeny,
+    /// Denied, but allowed by a rule in audit mode
+    #[serde(rename = "would_allow")]
+    WouldAllow,
+}
+
+impl Decision {
+    /// The name of the decision, as in the audit records
+    pub fn label(&self) -> &'static str {
+        match self {
+            Decision::Allowed => "allowed",
+            Decision::Denied => "denied",
+            Decision::WouldDeny => "would_deny",
+            Decision::WouldAllow => "would_allow",
+        }
+    }
+}
+
+/// One decision of the proxy, written as a JSON line
+#[derive(Serialize)]
+pub struct AuditRecord {
+    pub timestamp: String,
+    pub client: ClientIdentity,
+    pub method: Option<String>,
+    /// The normalized request target, or the raw one if it could not be normalized
+    pub path: Option<String>,
+    /// The rule that decided, the rule in audit mode for audited decisions
+    pub rule: Option<String>,
+    pub decision: Decision,
+    /// Why the request was denied, or would be in audit mode, when the
+    /// filters give a reason
+    pub reason: Option<String>,
+    /// The status of the response, `None` if Podman did not answer
+    pub status: Option<u16>,
+    /// The size of the request, `None` for streamed chunked bodies
+    pub request_bytes: Option<u64>,
+    /// The size of the response, `None` for streamed bodies of unknown size
+    pub response_bytes: Option<u64>,
+    /// The time until the head of the response
+    pub duration_ms: f64,
+}
+
+/// A request being handled, recorded once the head of its response is known
+pub struct PendingRequest {
+    pub client: ClientIdentity,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub rule: Option<String>,
+    pub decision: Decision,
+    pub reason: Option<String>,
+    pub request_bytes: Option<u64>,
+    pub started: Instant,
+}
+
+impl PendingRequest {
+    pub fn is_head(&self) -> bool {
+        self.method.as_deref() == Some("HEAD")
+    }
+
+    pub fn complete(self, status: Option<u16>, response_bytes: Option<u64>) -> AuditRecord {
+        AuditRecord {
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            client: self.client,
+            method: self.method,
+            path: self.path,
+            rule: self.rule,
+            decision: self.decision,
+            reason: self.reason,
+            status,
+            request_bytes: self.request_bytes,
+            response_bytes,
+            duration_ms: self.started.elapsed().as_micros() as f64 / 1000.0,
+        }
+    }
+}
+
+enum Entry {
+    Record(String),
+    /// Sent once the entries before it are written
+    Drained(oneshot::Sender<()>),
+}
+
+/// The sink of the audit records, shared by every client
+///
+/// Records are serialized by the clients and written by a single task, so that
+/// lines are never interleaved.
+#[derive(Clone)]
+pub struct AuditLog {
+    sender: Option<mpsc::UnboundedSender<Entry>>,
+}
+
+impl AuditLog {
+    /// An audit log dropping every record
+    pub fn disabled() -> Self {
+        AuditLog { sender: None }
+    }
+
+    /// Open the audit log, `-` writing to the standard output
+    ///
+    /// Records are appended to the file if it already exists.
+    pub async fn open(destination: &str) -> io::Result<Self> {
+        let writer: Box<dyn AsyncWrite + Send + Unpin> = match destination {
+            "-" => Box::new(io::stdout()),
+            path => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            ),
+        };
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_records(writer, receiver));
+
+        Ok(AuditLog {
+            sender: Some(sender),
+        })
+    }
+
+    /// Record an answered request, also counted in the metrics
+    pub fn record(&self, record: AuditRecord) {
+        metrics::observe_request(&record);
+
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+
+        match serde_json::to_string(&record) {
+            Ok(line) => {
+                let _ = sender.send(Entry::Record(line));
+            }
+            Err(e) => log::error!("Error serializing an audit record: {}", e),
+        }
+    }
+
+    /// Wait until the records sent so far are written
+    pub async fn drain(&self) {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+
+        let (drained_tx, drained_rx) = oneshot::channel();
+        if sender.send(Entry::Drained(drained_tx)).is_ok() {
+            let _ = drained_rx.await;
+        }
+    }
+}
+
+async fn write_records(
+    mut writer: Box<dyn AsyncWrite + Send + Unpin>,
+    mut receiver: mpsc::UnboundedReceiver<Entry>,
+) {
+    while let Some(entry) = receiver.recv().await {
+        let mut line = match entry {
+            Entry::Record(line) => line,
+            Entry::Drained(drained) => {
+                let _ = drained.send(());
+                continue;
+            }
+        };
+        line.push('\n');
+
+        if let Err(e) = writer.write_all(line.as_bytes()).await {
+            log::error!("Error writing to the audit log: {}", e);
+            continue;
+        }
+        if let Err(e) = writer.flush().await {
+            log::error!("Error writing to the audit log: {}", e);
+        }
+    }
+}
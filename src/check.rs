@@ -0,0 +1,502 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::ops::Range;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use toml_edit::{ImDocument, Item, Table, TableLike, Value};
+
+use crate::config::{self, Config, ConfigParsingError, Mode, Rule, METHODS};
+use crate::images::ImagePolicy;
+use crate::policy;
+
+lazy_static! {
+    static ref VERSIONED_PATTERN: Regex = Regex::new(r"^\^/v[0-9]").unwrap();
+}
+
+/// Patterns matching every request path
+const MATCH_ALL_PATTERNS: [&str; 7] = ["", "^", ".*", "^.*", "^.*$", "^/", "^/.*$"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in the config, `path` being its location such as `filters.get.rules[1]`
+struct Diagnostic {
+    severity: Severity,
+    path: Option<String>,
+    message: String,
+}
+
+/// A TOML document a diagnostic can be located in
+struct Source {
+    name: String,
+    text: String,
+    document: ImDocument<String>,
+}
+
+impl Source {
+    fn parse(name: String, text: &str) -> Option<Self> {
+        let document = ImDocument::parse(text.to_string()).ok()?;
+
+        Some(Source {
+            name,
+            text: text.to_string(),
+            document,
+        })
+    }
+
+    fn position(&self, offset: usize) -> String {
+        position(&self.name, &self.text, offset)
+    }
+}
+
+/// Format a byte offset in a text as `name:line:column`
+fn position(name: &str, text: &str, offset: usize) -> String {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+
+    format!("{}:{}:{}", name, line, column)
+}
+
+/// A step of a path in the config
+#[derive(Clone, Copy)]
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+fn segments(path: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+
+    for part in path.split('.') {
+        let mut indexes = part.split('[');
+        segments.push(Segment::Key(indexes.next().unwrap_or_default()));
+        segments.extend(
+            indexes
+                .filter_map(|index| index.trim_end_matches(']').parse().ok())
+                .map(Segment::Index),
+        );
+    }
+
+    segments
+}
+
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    Item(&'a Item),
+    Table(&'a Table),
+    Value(&'a Value),
+}
+
+impl<'a> Node<'a> {
+    fn table(self) -> Option<&'a dyn TableLike> {
+        match self {
+            Node::Item(item) => item.as_table_like(),
+            Node::Table(table) => Some(table),
+            Node::Value(value) => value.as_inline_table().map(|t| t as &dyn TableLike),
+        }
+    }
+
+    fn span(self) -> Option<Range<usize>> {
+        match self {
+            Node::Item(item) => item.span(),
+            Node::Table(table) => table.span(),
+            Node::Value(value) => value.span(),
+        }
+    }
+}
+
+/// The outcome of following a path in a document
+enum Lookup {
+    Found(Option<Range<usize>>),
+    Missing,
+    /// An index past the end of an array, with its position in the path and
+    /// the index it has in the array that follows, e.g. the one of a profile
+    Beyond(usize, usize),
+}
+
+fn lookup(document: &Table, segments: &[Segment]) -> Lookup {
+    let mut node = Node::Table(document);
+    let mut span = None;
+
+    for (position, segment) in segments.iter().enumerate() {
+        node = match *segment {
+            Segment::Key(key) => match node.table().and_then(|table| table.get_key_value(key)) {
+                Some((key, item)) => {
+                    span = key.span().or(span);
+                    Node::Item(item)
+                }
+                None => return Lookup::Missing,
+            },
+            Segment::Index(index) => {
+                let (length, next) = match node {
+                    Node::Item(Item::ArrayOfTables(tables)) => {
+                        (tables.len(), tables.get(index).map(Node::Table))
+                    }
+                    Node::Item(Item::Value(Value::Array(array)))
+                    | Node::Value(Value::Array(array)) => {
+                        (array.len(), array.get(index).map(Node::Value))
+                    }
+                    _ => return Lookup::Missing,
+                };
+
+                match next {
+                    Some(next) => {
+                        span = next.span().or(span);
+                        next
+                    }
+                    None => return Lookup::Beyond(position, index - length),
+                }
+            }
+        };
+    }
+
+    Lookup::Found(span)
+}
+
+/// Collects the diagnostics of a config and locates them in its file or profile
+struct Checker {
+    config_path: String,
+    local: Option<Source>,
+    profile: Option<Source>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn report(&mut self, severity: Severity, path: Option<String>, message: String) {
+        let is_duplicate = self.diagnostics.iter().any(|diagnostic| {
+            diagnostic.severity == severity
+                && diagnostic.path == path
+                && diagnostic.message == message
+        });
+
+        if !is_duplicate {
+            self.diagnostics.push(Diagnostic {
+                severity,
+                path,
+                message,
+            });
+        }
+    }
+
+    /// Find a path in the config file, or in the profile for the values it brings
+    ///
+    /// Arrays of the profile come after those of the config file once merged.
+    fn locate(&self, path: &str) -> String {
+        let mut segments = segments(path);
+
+        if let Some(local) = &self.local {
+            match lookup(local.document.as_table(), &segments) {
+                Lookup::Found(Some(span)) => return local.position(span.start),
+                Lookup::Found(None) => return self.config_path.clone(),
+                Lookup::Beyond(position, index) => segments[position] = Segment::Index(index),
+                Lookup::Missing => {}
+            }
+        }
+
+        if let Some(profile) = &self.profile {
+            if let Lookup::Found(Some(span)) = lookup(profile.document.as_table(), &segments) {
+                return profile.position(span.start);
+            }
+        }
+
+        self.config_path.clone()
+    }
+
+    fn print(&self) {
+        for diagnostic in &self.diagnostics {
+            match &diagnostic.path {
+                Some(path) => println!(
+                    "{}: {}: {}: {}",
+                    diagnostic.severity,
+                    self.locate(path),
+                    path,
+                    diagnostic.message
+                ),
+                None => println!(
+                    "{}: {}: {}",
+                    diagnostic.severity, self.config_path, diagnostic.message
+                ),
+            }
+        }
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == severity)
+            .count()
+    }
+
+    fn check_config(&mut self, config: &Config) {
+        for (path, error) in config::filters_errors(&config.filters) {
+            self.report(Severity::Error, Some(path), error.to_string());
+        }
+
+        let mut checked: HashSet<String> = HashSet::new();
+        for method in METHODS {
+            let rules = config.filters.located_method_rules(method);
+
+            for (path, rule) in &rules {
+                if !checked.insert(path.clone()) {
+                    continue;
+                }
+
+                if let Err(e) = policy::validate_rule(method, rule) {
+                    let message = format!("rule '{}': {}", rule.name, rule_error_reason(&e));
+                    self.report(Severity::Error, Some(path.clone()), message);
+                }
+
+                for warning in rule_warnings(path, rule) {
+                    self.report(Severity::Warning, Some(path.clone()), warning);
+                }
+            }
+
+            self.check_shadowed_rules(method, &rules);
+        }
+
+        if let Some(images) = &config.images {
+            for (index, e) in ImagePolicy::errors(images) {
+                let path = format!("images.allowed[{}]", index);
+                self.report(Severity::Error, Some(path), e.to_string());
+            }
+        }
+
+        for (index, listener) in config.listeners.iter().enumerate() {
+            let path = format!("listeners[{}]", index);
+            if let Err(reason) = listener.check() {
+                self.report(Severity::Error, Some(path.clone()), reason);
+            }
+
+            self.check_own_policy(
+                &path,
+                listener.config.as_deref(),
+                listener.profile.as_deref(),
+            );
+        }
+
+        for (index, client) in config.clients.iter().enumerate() {
+            let path = format!("clients[{}]", index);
+            if let Err(reason) = client.check(&config.listeners) {
+                self.report(Severity::Error, Some(path.clone()), reason);
+            }
+
+            self.check_own_policy(&path, client.config.as_deref(), client.profile.as_deref());
+        }
+    }
+
+    /// Check the policy of a listener or client, from its config file or profile
+    fn check_own_policy(&mut self, path: &str, config_path: Option<&str>, profile: Option<&str>) {
+        let result = config::get_own_config(config_path, profile).and_then(|config| {
+            config
+                .map(|config| config::compile_policy(&config))
+                .transpose()
+        });
+        let e = match result {
+            Ok(_) => return,
+            Err(e) => e,
+        };
+
+        // Regex errors already include their source
+        let mut message = match (&e, e.source()) {
+            (ConfigParsingError::RegexError(_), _) | (_, None) => e.to_string(),
+            (_, Some(source)) => format!("{}: {}", e, source),
+        };
+        let field = match (&e, config_path) {
+            (ConfigParsingError::ProfileError(_), _) | (_, None) => "profile",
+            (_, Some(config_path)) => {
+                message = format!("{}: {}", config_path, message);
+                "config"
+            }
+        };
+
+        self.report(
+            Severity::Error,
+            Some(format!("{}.{}", path, field)),
+            message,
+        );
+    }
+
+    /// Warn about rules after an unconditional rule matching the same paths
+    fn check_shadowed_rules(&mut self, method: &str, rules: &[(String, Rule)]) {
+        for (i, (path, rule)) in rules.iter().enumerate() {
+            // Routes of groups overlap by design
+            if rule.name.starts_with("group:") {
+                continue;
+            }
+
+            let shadowing = rules[..i].iter().find(|(_, earlier)| {
+                earlier.mode == Mode::Enforce
+                    && earlier.api_version.is_none()
+                    && earlier.query.is_empty()
+                    && (matches_all(earlier)
+                        || (earlier.regex == rule.regex
+                            && earlier.canonical == rule.canonical
+                            && earlier.route == rule.route))
+            });
+
+            if let Some((earlier_path, earlier)) = shadowing {
+                let message = format!(
+                    "rule '{}' can never match {} requests, rule '{}' at {} matches them first",
+                    rule.name, method, earlier.name, earlier_path
+                );
+                self.report(Severity::Warning, Some(path.clone()), message);
+            }
+        }
+    }
+}
+
+/// Get the reason of a rule compilation error, the rule being reported apart
+fn rule_error_reason(error: &ConfigParsingError) -> String {
+    match error {
+        ConfigParsingError::RuleError(_, _, reason) => reason.clone(),
+        ConfigParsingError::RegexError(e) => match e.source() {
+            Some(source) => source.to_string(),
+            None => e.to_string(),
+        },
+        e => e.to_string(),
+    }
+}
+
+/// Check if a rule matches every path of its method
+fn matches_all(rule: &Rule) -> bool {
+    [&rule.regex, &rule.canonical]
+        .into_iter()
+        .flatten()
+        .any(|pattern| MATCH_ALL_PATTERNS.contains(&pattern.as_str()))
+}
+
+/// Get the suspicious patterns and constraints of a rule
+fn rule_warnings(path: &str, rule: &Rule) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    // The legacy `allowed`/`regex` pair of a method
+    if path.ends_with(".regex") && rule.regex.as_deref() == Some("") {
+        warnings.push(String::from(
+            "allowed = true with an empty regex allows every path",
+        ));
+        return warnings;
+    }
+
+    for (field, pattern) in [("regex", &rule.regex), ("canonical", &rule.canonical)] {
+        let pattern = match pattern {
+            Some(pattern) => pattern,
+            None => continue,
+        };
+
+        if !pattern.starts_with('^') || !pattern.ends_with('$') {
+            warnings.push(format!(
+                "{} {} is not anchored with ^ and $, it matches any path containing it",
+                field, pattern
+            ));
+        }
+
+        if pattern
+            .strip_prefix('^')
+            .and_then(|rest| rest.chars().next())
+            .is_some_and(|c| c.is_ascii_alphanumeric())
+        {
+            warnings.push(format!(
+                "{} {} can never match, paths start with /",
+                field, pattern
+            ));
+        }
+
+        if field == "canonical" && VERSIONED_PATTERN.is_match(pattern) {
+            warnings.push(format!(
+                "canonical {} can never match, canonical paths have no version prefix",
+                pattern
+            ));
+        }
+    }
+
+    for (name, query_rule) in &rule.query {
+        if query_rule.required && query_rule.forbidden {
+            warnings.push(format!(
+                "query parameter {} is both required and forbidden, the rule can never match",
+                name
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// Check a config and print every error and warning found
+///
+/// Returns `false` if an error was found, or a warning when `deny_warnings` is set.
+pub fn check_config(config_path: &str, profile: Option<&str>, deny_warnings: bool) -> bool {
+    let text = match fs::read_to_string(config_path) {
+        Ok(text) => text,
+        Err(e) if profile.is_some() && e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            println!("error: {}: failed to read TOML file: {}", config_path, e);
+            return false;
+        }
+    };
+
+    let mut checker = Checker {
+        config_path: config_path.to_string(),
+        local: Source::parse(config_path.to_string(), &text),
+        profile: None,
+        diagnostics: Vec::new(),
+    };
+
+    // Parsing the file on its own locates syntax and type errors
+    if let Err(e) = toml::from_str::<Config>(&text) {
+        let location = match e.span() {
+            Some(span) => position(config_path, &text, span.start),
+            None => config_path.to_string(),
+        };
+
+        println!("error: {}: {}", location, e.message().trim_end());
+        return false;
+    }
+
+    match config::load_config(config_path, profile) {
+        Ok(config) => {
+            if let Some(reference) = &config.profile {
+                checker.profile = config::profile_source(reference)
+                    .and_then(|source| Source::parse(format!("profile {}", reference), source));
+            }
+
+            checker.check_config(&config);
+        }
+        Err(e @ ConfigParsingError::ProfileError(_)) => {
+            let path = profile.is_none().then(|| String::from("profile"));
+            checker.report(Severity::Error, path, e.to_string());
+        }
+        Err(e) => {
+            let message = match e.source() {
+                Some(source) => format!("{}: {}", e, source),
+                None => e.to_string(),
+            };
+            checker.report(Severity::Error, None, message);
+        }
+    }
+
+    checker.print();
+
+    let errors = checker.count(Severity::Error);
+    let warnings = checker.count(Severity::Warning);
+    println!(
+        "{}: {} error(s), {} warning(s)",
+        config_path, errors, warnings
+    );
+
+    errors == 0 && (!deny_warnings || warnings == 0)
+}
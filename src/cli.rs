@@ -1,4 +1,5 @@
 use clap::{Args, Parser, Subcommand};
+use std::time::Duration;
 
 const DEFAULT_PODMAN_PATH: &str = "/run/podman/podman.sock";
 const DEFAULT_SOCKET_PATH: &str = "/var/run/safe-podman.sock";
@@ -14,10 +15,34 @@ pub struct Cli {
     #[arg(short, long, default_value_t = DEFAULT_PODMAN_PATH.to_string())]
     pub podman_path: String,
 
+    /// Retry connecting to the Podman socket for a client, with an exponential backoff, before answering 503
+    #[arg(long, default_value_t = 0)]
+    pub upstream_retries: u32,
+
     /// The path to the TOML configuration file
     #[arg(short, long, default_value_t = String::from("./config.toml"))]
     pub config_path: String,
 
+    /// A bundled profile to extend instead of the one of the config file, e.g. traefik
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Reload the configuration when the file changes, in addition to SIGHUP
+    #[arg(long, default_value_t = false)]
+    pub watch_config: bool,
+
+    /// Write a JSON audit record of every request to a file, or to the standard output with -
+    #[arg(long)]
+    pub audit_log: Option<String>,
+
+    /// Serve /metrics, /healthz and /readyz on a TCP address, e.g. 127.0.0.1:9100, or a Unix socket path
+    #[arg(long)]
+    pub admin_listen: Option<String>,
+
+    /// How long in-flight requests are waited for on SIGTERM or SIGINT, e.g. 30s
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    pub shutdown_timeout: Duration,
+
     #[command(subcommand)]
     pub proxy: Proxy,
 }
@@ -28,6 +53,34 @@ pub enum Proxy {
 
     /// Start the proxy as a TCP Socket
     Inet(InetProxyArgs),
+
+    /// Start the proxy on a socket passed by systemd socket activation
+    Systemd(SystemdProxyArgs),
+
+    /// Start the proxy on the [[listeners]] of the configuration
+    Serve,
+
+    /// Check the configuration without starting the proxy
+    Check(CheckArgs),
+
+    /// Evaluate a sample request against the configuration
+    Explain(ExplainArgs),
+
+    /// Allow every request and write a policy allowing the requests seen on exit
+    Learn(LearnArgs),
+}
+
+/// The socket of the proxy, for the commands starting it
+#[derive(Subcommand, Debug)]
+pub enum Listener {
+    /// Listen on a Unix Socket
+    Unix(UnixProxyArgs),
+
+    /// Listen on a TCP Socket
+    Inet(InetProxyArgs),
+
+    /// Listen on a socket passed by systemd socket activation
+    Systemd(SystemdProxyArgs),
 }
 
 #[derive(Args, Debug)]
@@ -39,6 +92,18 @@ pub struct UnixProxyArgs {
     /// Replace the socket file if it already exists
     #[arg(short, long, default_value_t = false)]
     pub replace: bool,
+
+    /// The octal permission mode of the socket, e.g. 0660
+    #[arg(short, long)]
+    pub mode: Option<String>,
+
+    /// The user name or uid owning the socket
+    #[arg(long)]
+    pub owner: Option<String>,
+
+    /// The group name or gid owning the socket
+    #[arg(long)]
+    pub group: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -50,6 +115,63 @@ pub struct InetProxyArgs {
     /// The port the protected socket will listen on
     #[arg(short, long, default_value_t = DEFAULT_SOCKET_PORT, value_parser = clap::value_parser!(u16).range(1..65535))]
     pub port: u16,
+
+    /// The PEM certificate chain of the proxy, enabling TLS
+    #[arg(long)]
+    pub tlscert: Option<String>,
+
+    /// The PEM private key of the proxy
+    #[arg(long)]
+    pub tlskey: Option<String>,
+
+    /// The PEM bundle of the CAs signing the client certificates
+    #[arg(long)]
+    pub tlscacert: Option<String>,
+
+    /// Require client certificates signed by the CAs of --tlscacert
+    #[arg(long, default_value_t = false)]
+    pub tlsverify: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct SystemdProxyArgs {
+    /// The name of the socket passed by systemd, required if it passes several
+    #[arg(short, long)]
+    pub name: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckArgs {
+    /// Fail on warnings too
+    #[arg(long, default_value_t = false)]
+    pub deny_warnings: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ExplainArgs {
+    /// The HTTP method of the request, e.g. GET
+    pub method: String,
+
+    /// The request target, e.g. /v1.41/containers/json?all=1
+    pub target: String,
+
+    /// A request header, e.g. "Content-Type: application/json"
+    #[arg(short = 'H', long = "header")]
+    pub headers: Vec<String>,
+
+    /// A file holding the request body
+    #[arg(short, long)]
+    pub body: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct LearnArgs {
+    /// The path of the TOML policy written on exit
+    #[arg(short, long, default_value_t = String::from("./learned.toml"))]
+    pub output: String,
+
+    #[command(subcommand)]
+    pub listener: Listener,
 }
 
 /// Parse the command line arguments
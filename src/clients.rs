@@ -0,0 +1,126 @@
+use anyhow::{bail, Context};
+
+use crate::config::{Client, Listener};
+use crate::filter::FiltersHandler;
+use crate::proxy::ClientIdentity;
+use crate::reload;
+
+impl Client {
+    /// Get the name of the client in the logs, its criteria by default
+    pub fn display_name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        let mut criteria = Vec::new();
+        if let Some(uid) = self.uid {
+            criteria.push(format!("uid={}", uid));
+        }
+        if let Some(gid) = self.gid {
+            criteria.push(format!("gid={}", gid));
+        }
+        if let Some(exe) = &self.exe {
+            criteria.push(format!("exe={}", exe));
+        }
+        criteria.join(" ")
+    }
+
+    /// Check that the entry sets a uid or gid, a policy and an existing listener
+    ///
+    /// The executable is read once the client is connected, a process can then
+    /// exec another one while a child keeps the connection: `exe` alone does
+    /// not identify a client.
+    ///
+    /// # Errors
+    ///
+    /// The reason is returned if it does not.
+    pub fn check(&self, listeners: &[Listener]) -> Result<(), String> {
+        if self.uid.is_none() && self.gid.is_none() {
+            return Err(String::from(
+                "set uid or gid, exe alone does not identify a client",
+            ));
+        }
+        if self.config.is_none() && self.profile.is_none() {
+            return Err(String::from("set config or profile"));
+        }
+        if let Some(listener) = &self.listener {
+            if !listeners
+                .iter()
+                .any(|entry| entry.name.as_ref() == Some(listener))
+            {
+                return Err(format!("no listener named {}", listener));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if a client of a listener matches every criterion given
+    ///
+    /// TCP clients and unknown credentials never match.
+    fn matches(&self, client: &ClientIdentity, listener: Option<&str>) -> bool {
+        let ClientIdentity::Unix { uid, gid, exe, .. } = client else {
+            return false;
+        };
+
+        self.listener
+            .as_deref()
+            .is_none_or(|expected| listener == Some(expected))
+            && self.uid.is_none_or(|expected| *uid == Some(expected))
+            && self.gid.is_none_or(|expected| *gid == Some(expected))
+            && self
+                .exe
+                .as_ref()
+                .is_none_or(|expected| exe.as_ref() == Some(expected))
+    }
+}
+
+/// The policies of the Unix clients, by their credentials
+pub struct ClientPolicies {
+    policies: Vec<(Client, FiltersHandler)>,
+}
+
+impl ClientPolicies {
+    /// Load the policy of every client of the config
+    pub fn load(
+        clients: &[Client],
+        listeners: &[Listener],
+        watch_config: bool,
+    ) -> anyhow::Result<Self> {
+        let mut policies = Vec::new();
+
+        for (index, client) in clients.iter().enumerate() {
+            if let Err(reason) = client.check(listeners) {
+                bail!("clients[{}]: {}", index, reason);
+            }
+
+            let filters_handler = reload::load_filters(
+                client.config.as_deref(),
+                client.profile.as_deref(),
+                watch_config,
+            )
+            .with_context(|| format!("Failed to load the policy of clients[{}]", index))?;
+
+            if let Some(filters_handler) = filters_handler {
+                policies.push((client.clone(), filters_handler));
+            }
+        }
+
+        Ok(ClientPolicies { policies })
+    }
+
+    /// Get the filters of the first client entry matching a client of a listener, if any
+    ///
+    /// `listener` is the name of the `[[listeners]]` entry, `None` for the
+    /// listener of the command line.
+    pub fn select(
+        &self,
+        client: &ClientIdentity,
+        listener: Option<&str>,
+    ) -> Option<(&Client, &FiltersHandler)> {
+        self.policies
+            .iter()
+            .find(|(entry, _)| entry.matches(client, listener))
+            .map(|(entry, filters_handler)| (entry, filters_handler))
+    }
+}
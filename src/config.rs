@@ -1,27 +1,42 @@
+use crate::policy::Policy;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs;
+use std::sync::Arc;
 use thiserror::Error;
 
+mod groups;
+mod profiles;
+
+/// HTTP methods that can be filtered by the proxy
+pub const METHODS: [&str; 6] = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"];
+
 #[derive(Error, Debug)]
 pub struct HTTPRegexParseError {
     method: String,
+    rule: String,
     #[source]
     source: regex::Error,
 }
 
 impl HTTPRegexParseError {
-    pub fn new(method: String, source: regex::Error) -> Self {
-        HTTPRegexParseError { method, source }
+    pub fn new(method: String, rule: String, source: regex::Error) -> Self {
+        HTTPRegexParseError {
+            method,
+            rule,
+            source,
+        }
     }
 }
 
 impl std::fmt::Display for HTTPRegexParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "[{}] {}", self.method, self.source)
+        write!(f, "[{}] {}: {}", self.method, self.rule, self.source)
     }
 }
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum ConfigParsingError {
     #[error("failed to parse TOML file")]
     ParseError(#[from] toml::de::Error),
@@ -29,67 +44,564 @@ pub enum ConfigParsingError {
     ReadError(#[from] std::io::Error),
     #[error(transparent)]
     RegexError(#[from] HTTPRegexParseError),
+    #[error("rule '{0}' references unknown method '{1}'")]
+    MethodError(String, String),
+    #[error("[{0}] {1}: {2}")]
+    RuleError(String, String, String),
+    #[error("unknown group '{0}'")]
+    GroupError(String),
+    #[error("unknown profile '{0}'")]
+    ProfileError(String),
+    #[error("[images] allowed entry {0}: {1}")]
+    ImagePatternError(usize, #[source] regex::Error),
 }
 
 #[derive(Deserialize, Clone)]
 pub struct Config {
+    /// Bundled profile the config extends, e.g. `traefik` or `traefik@1`
+    pub profile: Option<String>,
+    /// In audit mode, denied requests are logged but still forwarded
+    #[serde(default)]
+    pub mode: Mode,
+    #[serde(default)]
     pub filters: Filters,
+    #[serde(default)]
+    pub container_create: ContainerCreate,
+    /// Images that can be pulled or run, any image if absent
+    pub images: Option<Images>,
+    #[serde(default)]
+    pub socket: Socket,
+    #[serde(default)]
+    pub tls: Tls,
+    /// Sockets served by the `serve` command
+    #[serde(default)]
+    pub listeners: Vec<Listener>,
+    /// Policies of the Unix clients, selected by their credentials
+    #[serde(default)]
+    pub clients: Vec<Client>,
 }
 
-#[derive(Deserialize, Clone)]
+/// A socket served by the proxy, with its own policy
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Listener {
+    /// Name of the listener in the logs
+    pub name: Option<String>,
+    /// Path of a Unix socket
+    pub unix: Option<String>,
+    /// TCP address, e.g. `127.0.0.1:8787`
+    pub inet: Option<String>,
+    /// Replace the Unix socket file if it already exists
+    #[serde(default)]
+    pub replace: bool,
+    /// Permissions of the Unix socket, those of `[socket]` by default
+    #[serde(flatten)]
+    pub socket: Socket,
+    /// Config file holding the policy of the listener
+    pub config: Option<String>,
+    /// Bundled profile of the listener, extended by its config file if any
+    pub profile: Option<String>,
+}
+
+/// The policy of the Unix clients matching every criterion given
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Client {
+    /// Name of the client in the logs
+    pub name: Option<String>,
+    pub uid: Option<u32>,
+    /// Primary group of the client process
+    pub gid: Option<u32>,
+    /// Path of the executable of the client process, e.g. `/usr/bin/traefik`
+    pub exe: Option<String>,
+    /// Name of the `[[listeners]]` entry the client entry applies to, every
+    /// listener by default
+    pub listener: Option<String>,
+    /// Config file holding the policy of the client
+    pub config: Option<String>,
+    /// Bundled profile of the client, extended by its config file if any
+    pub profile: Option<String>,
+}
+
+/// Permissions of the Unix socket, overridden by the command line
+#[derive(Deserialize, Clone, Default, PartialEq)]
+pub struct Socket {
+    /// Octal permission mode, e.g. `"0660"`
+    pub mode: Option<String>,
+    /// User name or uid
+    pub owner: Option<String>,
+    /// Group name or gid
+    pub group: Option<String>,
+}
+
+/// TLS of the TCP sockets, overridden by the command line
+#[derive(Deserialize, Clone, Default, PartialEq)]
+pub struct Tls {
+    /// PEM certificate chain of the proxy, TLS is enabled when set
+    pub cert: Option<String>,
+    /// PEM private key of the proxy
+    pub key: Option<String>,
+    /// PEM bundle of the CAs signing the client certificates
+    pub ca_cert: Option<String>,
+    /// Require client certificates signed by `ca_cert`
+    #[serde(default)]
+    pub verify: bool,
+}
+
+/// Restrictions on the body of container create requests, everything is denied by default
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ContainerCreate {
+    pub allow_privileged: bool,
+    pub allow_capabilities: bool,
+    pub allow_host_network: bool,
+    pub allow_host_pid: bool,
+    pub allow_host_ipc: bool,
+    pub allow_devices: bool,
+    /// Host paths, and their children, that can be bind mounted
+    pub allowed_bind_paths: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Default, PartialEq)]
+pub struct Images {
+    /// Allow local image IDs, which do not name a registry, to create containers
+    #[serde(default)]
+    pub allow_ids: bool,
+    #[serde(default)]
+    pub allowed: Vec<AllowedImage>,
+}
+
+/// An allowed image, every pattern given must match
+///
+/// `registry`, `repository` and `tag` are globs, `tag` also matches digests.
+/// `regex` matches the full normalized reference.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+pub struct AllowedImage {
+    pub registry: Option<String>,
+    pub repository: Option<String>,
+    pub tag: Option<String>,
+    pub regex: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Default)]
 pub struct Filters {
+    /// Built-in groups of routes, enabled groups are allowed after every other
+    /// rule while disabled groups are denied before them
+    #[serde(default)]
+    pub groups: BTreeMap<String, bool>,
+    /// Rules applying to every method, evaluated before the method rules
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
     pub get: Proxy,
+    #[serde(default)]
     pub head: Proxy,
+    #[serde(default)]
     pub post: Proxy,
+    #[serde(default)]
     pub put: Proxy,
+    #[serde(default)]
     pub patch: Proxy,
+    #[serde(default)]
     pub delete: Proxy,
 }
 
-#[derive(Deserialize, Clone)]
+impl Filters {
+    /// Get the filters of an HTTP method, `None` if the method is not supported
+    pub fn method(&self, method: &str) -> Option<&Proxy> {
+        match method {
+            "GET" => Some(&self.get),
+            "HEAD" => Some(&self.head),
+            "POST" => Some(&self.post),
+            "PUT" => Some(&self.put),
+            "PATCH" => Some(&self.patch),
+            "DELETE" => Some(&self.delete),
+            _ => None,
+        }
+    }
+
+    /// Get the ordered rules applying to an HTTP method
+    ///
+    /// Disabled groups come first as deny rules, then the global rules, the
+    /// rules of the method, the legacy `allowed`/`regex` pair, if enabled, as
+    /// an allow rule and finally the enabled groups as allow rules.
+    pub fn method_rules(&self, method: &str) -> Vec<Rule> {
+        self.located_method_rules(method)
+            .into_iter()
+            .map(|(_, rule)| rule)
+            .collect()
+    }
+
+    /// Get the ordered rules applying to an HTTP method with their location
+    /// in the config, e.g. `filters.get.rules[1]`
+    pub fn located_method_rules(&self, method: &str) -> Vec<(String, Rule)> {
+        let proxy = match self.method(method) {
+            Some(proxy) => proxy,
+            None => return Vec::new(),
+        };
+        let section = format!("filters.{}", method.to_lowercase());
+
+        let mut rules = self.group_rules(method, false);
+
+        rules.extend(
+            self.rules
+                .iter()
+                .enumerate()
+                .filter(|(_, rule)| rule.applies_to(method))
+                .map(|(i, rule)| (format!("filters.rules[{}]", i), rule.clone())),
+        );
+
+        rules.extend(
+            proxy
+                .rules
+                .iter()
+                .enumerate()
+                .map(|(i, rule)| (format!("{}.rules[{}]", section, i), rule.clone())),
+        );
+
+        if proxy.allowed {
+            let rule = Rule {
+                name: method.to_lowercase(),
+                action: RuleAction::Allow,
+                mode: Mode::Enforce,
+                regex: Some(proxy.regex.clone()),
+                canonical: None,
+                route: None,
+                api_version: None,
+                methods: Vec::new(),
+                query: BTreeMap::new(),
+            };
+            rules.push((format!("{}.regex", section), rule));
+        }
+
+        rules.extend(self.group_rules(method, true));
+
+        rules
+    }
+
+    /// Expand the groups toggled to `enabled` into rules for an HTTP method
+    fn group_rules(&self, method: &str, enabled: bool) -> Vec<(String, Rule)> {
+        let action = match enabled {
+            true => RuleAction::Allow,
+            false => RuleAction::Deny,
+        };
+
+        self.groups
+            .iter()
+            .filter(|(_, toggle)| **toggle == enabled)
+            .filter_map(|(name, _)| groups::find(name))
+            .flat_map(|group| {
+                group
+                    .all_routes()
+                    .into_iter()
+                    .filter(|(route_method, _)| *route_method == method)
+                    .map(move |(_, route)| {
+                        let rule = Rule::route(format!("group:{}", group.name), action, route);
+                        (format!("filters.groups.{}", group.name), rule)
+                    })
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
 pub struct Proxy {
+    #[serde(default)]
     pub allowed: bool,
+    #[serde(default)]
     pub regex: String,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
 }
 
-/// Check if the regex strings in the config are valid
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Allow,
+    Deny,
+}
+
+/// Whether the decisions of a rule, or of the whole config, are enforced or only logged
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Enforce,
+    Audit,
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Mode::Enforce => write!(f, "enforce"),
+            Mode::Audit => write!(f, "audit"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub action: RuleAction,
+    /// A rule in audit mode is skipped, the requests it would decide differently are logged
+    #[serde(default)]
+    pub mode: Mode,
+    /// Pattern matched against the normalized path, version prefix included
+    pub regex: Option<String>,
+    /// Pattern matched against the normalized path without its version prefix
+    pub canonical: Option<String>,
+    /// Template matched against the normalized path without its version prefix,
+    /// e.g. `/containers/{id}/logs`
+    pub route: Option<String>,
+    /// Range of API versions the rule applies to, e.g. `>=1.40, <1.44`
+    pub api_version: Option<String>,
+    /// Methods a global rule applies to, all of them if empty
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Constraints on the query parameters, by parameter name
+    #[serde(default)]
+    pub query: BTreeMap<String, QueryRule>,
+}
+
+/// A constraint on a query parameter, a rule only matches if all of them are satisfied
+///
+/// `values` and `regex` apply to every value of the parameter when it is present.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct QueryRule {
+    pub required: bool,
+    pub forbidden: bool,
+    pub values: Vec<String>,
+    pub regex: Option<String>,
+}
+
+impl Rule {
+    fn route(name: String, action: RuleAction, route: &str) -> Self {
+        Rule {
+            name,
+            action,
+            mode: Mode::Enforce,
+            regex: None,
+            canonical: None,
+            route: Some(route.to_string()),
+            api_version: None,
+            methods: Vec::new(),
+            query: BTreeMap::new(),
+        }
+    }
+
+    fn applies_to(&self, method: &str) -> bool {
+        self.methods.is_empty() || self.methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+    }
+}
+
+/// Check if the groups exist and the global rules reference known methods
 ///
 /// # Errors
 ///
-/// If an invalid pattern is found in the config, an error is returned.
-/// An error is also returned if the pattern is valid, but would
-/// produce a regex that is bigger than the size limit configured in the regex library.
-fn check_config_filters(filters: &Filters) -> Result<(), HTTPRegexParseError> {
-    let methods = vec!["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"];
-    for method in methods {
-        let proxy = match method {
-            "GET" => &filters.get,
-            "HEAD" => &filters.head,
-            "POST" => &filters.post,
-            "PUT" => &filters.put,
-            "PATCH" => &filters.patch,
-            "DELETE" => &filters.delete,
-            _ => return Ok(()),
-        };
+/// An error is returned for the first unknown group or global rule
+/// referencing an unknown method.
+fn check_config_filters(filters: &Filters) -> Result<(), ConfigParsingError> {
+    match filters_errors(filters).into_iter().next() {
+        Some((_, error)) => Err(error),
+        None => Ok(()),
+    }
+}
 
-        if proxy.allowed {
-            match regex::Regex::new(&proxy.regex) {
-                Ok(_) => {}
-                Err(e) => {
-                    return Err(HTTPRegexParseError::new(method.to_string(), e));
-                }
-            };
+/// Get every unknown group and global rule referencing an unknown method,
+/// with their location in the config
+pub fn filters_errors(filters: &Filters) -> Vec<(String, ConfigParsingError)> {
+    let mut errors = Vec::new();
+
+    for name in filters.groups.keys() {
+        if groups::find(name).is_none() {
+            errors.push((
+                format!("filters.groups.{}", name),
+                ConfigParsingError::GroupError(name.clone()),
+            ));
         }
     }
 
-    Ok(())
+    for (i, rule) in filters.rules.iter().enumerate() {
+        if let Some(method) = rule
+            .methods
+            .iter()
+            .find(|m| !METHODS.contains(&m.to_uppercase().as_str()))
+        {
+            errors.push((
+                format!("filters.rules[{}].methods", i),
+                ConfigParsingError::MethodError(rule.name.clone(), method.clone()),
+            ));
+        }
+    }
+
+    errors
 }
 
-pub fn get_config(path: &str) -> Result<Config, ConfigParsingError> {
-    let config_file = fs::read_to_string(path)?;
-    let config: Config = toml::from_str(&config_file)?;
+/// Compile the filters of the config into a policy shared by every client
+///
+/// # Errors
+///
+/// If an invalid pattern is found in the filters, an error is returned.
+pub fn compile_policy(config: &Config) -> Result<Arc<Policy>, ConfigParsingError> {
+    let policy = Policy::compile(config)?;
+
+    Ok(Arc::new(policy))
+}
+
+/// Read and check the config, merged over its profile
+///
+/// The `profile` argument takes precedence over the profile of the config file.
+/// When a profile is given, a missing config file is treated as empty.
+pub fn get_config(path: &str, profile: Option<&str>) -> Result<Config, ConfigParsingError> {
+    let config = load_config(path, profile)?;
 
     check_config_filters(&config.filters)?;
 
     Ok(config)
 }
+
+/// Read the config merged over its profile, without checking its filters
+pub fn load_config(path: &str, profile: Option<&str>) -> Result<Config, ConfigParsingError> {
+    let config_file = match fs::read_to_string(path) {
+        Ok(config_file) => config_file,
+        Err(e) if profile.is_some() && e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let local: toml::Value = toml::from_str(&config_file)?;
+
+    extend_profile(local, profile)
+}
+
+/// Get the config of a bundled profile, without a config file
+pub fn get_profile_config(profile: &str) -> Result<Config, ConfigParsingError> {
+    let config = extend_profile(toml::Value::Table(toml::Table::new()), Some(profile))?;
+
+    check_config_filters(&config.filters)?;
+
+    Ok(config)
+}
+
+/// Get the config of a listener or client with its own policy
+///
+/// Returns `None` without a config file nor a profile.
+pub fn get_own_config(
+    path: Option<&str>,
+    profile: Option<&str>,
+) -> Result<Option<Config>, ConfigParsingError> {
+    match (path, profile) {
+        (None, None) => Ok(None),
+        (Some(path), profile) => get_config(path, profile).map(Some),
+        (None, Some(profile)) => get_profile_config(profile).map(Some),
+    }
+}
+
+/// Merge a config with the profile it extends, the given one or else its own
+fn extend_profile(local: toml::Value, profile: Option<&str>) -> Result<Config, ConfigParsingError> {
+    let profile = profile
+        .map(String::from)
+        .or_else(|| local.get("profile")?.as_str().map(String::from));
+
+    let merged = match profile {
+        Some(reference) => {
+            let profile = profiles::find(&reference)
+                .ok_or_else(|| ConfigParsingError::ProfileError(reference.clone()))?;
+            let base: toml::Value = toml::from_str(profile.source)?;
+
+            let mut merged = profiles::merge(base, local);
+            if let toml::Value::Table(table) = &mut merged {
+                table.insert(String::from("profile"), toml::Value::String(reference));
+            }
+            merged
+        }
+        None => local,
+    };
+
+    let config: Config = merged.try_into()?;
+
+    Ok(config)
+}
+
+/// Get the TOML source of a bundled profile
+pub fn profile_source(reference: &str) -> Option<&'static str> {
+    profiles::find(reference).map(|profile| profile.source)
+}
+
+/// Get the method and route template of every route of the built-in groups
+pub fn group_routes() -> Vec<(&'static str, &'static str)> {
+    groups::GROUPS
+        .iter()
+        .flat_map(|group| group.routes.iter().copied())
+        .collect()
+}
+
+/// Summarize the changes between two configs, for the reload logs
+pub fn diff_summary(old: &Config, new: &Config) -> String {
+    let mut changes: Vec<String> = Vec::new();
+
+    if old.profile != new.profile {
+        changes.push(format!(
+            "profile {} -> {}",
+            old.profile.as_deref().unwrap_or("none"),
+            new.profile.as_deref().unwrap_or("none")
+        ));
+    }
+
+    if old.mode != new.mode {
+        changes.push(format!("mode {} -> {}", old.mode, new.mode));
+    }
+
+    for method in METHODS {
+        let old_rules = old.filters.method_rules(method);
+        let new_rules = new.filters.method_rules(method);
+        if old_rules == new_rules {
+            continue;
+        }
+
+        // Group rules share the name of their group, so names are reported once
+        let names = |rules: &[Rule], others: &[Rule], prefix: char| -> Vec<String> {
+            let mut names: Vec<String> = Vec::new();
+            for rule in rules {
+                let name = format!("{}{}", prefix, rule.name);
+                if !others.iter().any(|other| other.name == rule.name) && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            names
+        };
+        let mut renamed = names(&new_rules, &old_rules, '+');
+        renamed.extend(names(&old_rules, &new_rules, '-'));
+
+        match renamed.is_empty() {
+            true => changes.push(format!("{} rules modified", method)),
+            false => changes.push(format!("{} rules {}", method, renamed.join(" "))),
+        }
+    }
+
+    if old.container_create != new.container_create {
+        changes.push(String::from("container_create modified"));
+    }
+
+    if old.images != new.images {
+        changes.push(String::from("images modified"));
+    }
+
+    if old.socket != new.socket {
+        changes.push(String::from("socket modified, applied on restart"));
+    }
+
+    if old.tls != new.tls {
+        changes.push(String::from("tls modified, applied on restart"));
+    }
+
+    if old.listeners != new.listeners {
+        changes.push(String::from("listeners modified, applied on restart"));
+    }
+
+    if old.clients != new.clients {
+        changes.push(String::from("clients modified, applied on restart"));
+    }
+
+    match changes.is_empty() {
+        true => String::from("no changes"),
+        false => changes.join(", "),
+    }
+}
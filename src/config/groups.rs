@@ -0,0 +1,200 @@
+/// A named capability, the routes it needs by method
+pub struct Group {
+    pub name: &'static str,
+    pub routes: &'static [(&'static str, &'static str)],
+    /// Other groups whose routes are included
+    pub includes: &'static [&'static str],
+}
+
+pub const GROUPS: &[Group] = &[
+    Group {
+        name: "read_only",
+        routes: &[],
+        includes: &[
+            "ping",
+            "version",
+            "info",
+            "events",
+            "containers",
+            "logs",
+            "images",
+            "networks",
+            "volumes",
+            "pods",
+            "system",
+        ],
+    },
+    Group {
+        name: "ping",
+        routes: &[
+            ("GET", "/_ping"),
+            ("HEAD", "/_ping"),
+            ("GET", "/libpod/_ping"),
+            ("HEAD", "/libpod/_ping"),
+        ],
+        includes: &[],
+    },
+    Group {
+        name: "version",
+        routes: &[("GET", "/version"), ("GET", "/libpod/version")],
+        includes: &[],
+    },
+    Group {
+        name: "info",
+        routes: &[("GET", "/info"), ("GET", "/libpod/info")],
+        includes: &[],
+    },
+    Group {
+        name: "events",
+        routes: &[("GET", "/events"), ("GET", "/libpod/events")],
+        includes: &[],
+    },
+    Group {
+        name: "containers",
+        routes: &[
+            ("GET", "/containers/json"),
+            ("GET", "/containers/{id}/json"),
+            ("GET", "/containers/{id}/top"),
+            ("GET", "/containers/{id}/stats"),
+            ("GET", "/containers/{id}/changes"),
+            ("GET", "/libpod/containers/json"),
+            ("GET", "/libpod/containers/stats"),
+            ("GET", "/libpod/containers/{id}/json"),
+            ("GET", "/libpod/containers/{id}/top"),
+            ("GET", "/libpod/containers/{id}/stats"),
+            ("GET", "/libpod/containers/{id}/changes"),
+            ("GET", "/libpod/containers/{id}/exists"),
+            ("GET", "/libpod/containers/{id}/healthcheck"),
+        ],
+        includes: &[],
+    },
+    Group {
+        name: "logs",
+        routes: &[
+            ("GET", "/containers/{id}/logs"),
+            ("GET", "/libpod/containers/{id}/logs"),
+        ],
+        includes: &[],
+    },
+    Group {
+        name: "lifecycle",
+        routes: &[
+            ("POST", "/containers/{id}/start"),
+            ("POST", "/containers/{id}/stop"),
+            ("POST", "/containers/{id}/restart"),
+            ("POST", "/containers/{id}/kill"),
+            ("POST", "/containers/{id}/pause"),
+            ("POST", "/containers/{id}/unpause"),
+            ("POST", "/containers/{id}/wait"),
+            ("POST", "/libpod/containers/{id}/start"),
+            ("POST", "/libpod/containers/{id}/stop"),
+            ("POST", "/libpod/containers/{id}/restart"),
+            ("POST", "/libpod/containers/{id}/kill"),
+            ("POST", "/libpod/containers/{id}/pause"),
+            ("POST", "/libpod/containers/{id}/unpause"),
+            ("POST", "/libpod/containers/{id}/wait"),
+        ],
+        includes: &[],
+    },
+    Group {
+        name: "create",
+        routes: &[
+            ("POST", "/containers/create"),
+            ("DELETE", "/containers/{id}"),
+            ("POST", "/libpod/containers/create"),
+            ("DELETE", "/libpod/containers/{id}"),
+        ],
+        includes: &[],
+    },
+    Group {
+        name: "exec",
+        routes: &[
+            ("POST", "/containers/{id}/exec"),
+            ("POST", "/exec/{id}/start"),
+            ("POST", "/exec/{id}/resize"),
+            ("GET", "/exec/{id}/json"),
+            ("POST", "/libpod/containers/{id}/exec"),
+            ("POST", "/libpod/exec/{id}/start"),
+            ("POST", "/libpod/exec/{id}/resize"),
+            ("GET", "/libpod/exec/{id}/json"),
+        ],
+        includes: &[],
+    },
+    Group {
+        name: "build",
+        routes: &[("POST", "/build"), ("POST", "/libpod/build")],
+        includes: &[],
+    },
+    Group {
+        name: "images",
+        routes: &[
+            ("GET", "/images/json"),
+            ("GET", "/images/{name:*}/json"),
+            ("GET", "/images/{name:*}/history"),
+            ("GET", "/libpod/images/json"),
+            ("GET", "/libpod/images/{name:*}/json"),
+            ("GET", "/libpod/images/{name:*}/exists"),
+            ("GET", "/libpod/images/{name:*}/history"),
+        ],
+        includes: &[],
+    },
+    Group {
+        name: "pull",
+        routes: &[("POST", "/images/create"), ("POST", "/libpod/images/pull")],
+        includes: &[],
+    },
+    Group {
+        name: "networks",
+        routes: &[
+            ("GET", "/networks"),
+            ("GET", "/networks/{id}"),
+            ("GET", "/libpod/networks/json"),
+            ("GET", "/libpod/networks/{id}/json"),
+            ("GET", "/libpod/networks/{id}/exists"),
+        ],
+        includes: &[],
+    },
+    Group {
+        name: "volumes",
+        routes: &[
+            ("GET", "/volumes"),
+            ("GET", "/volumes/{id}"),
+            ("GET", "/libpod/volumes/json"),
+            ("GET", "/libpod/volumes/{id}/json"),
+            ("GET", "/libpod/volumes/{id}/exists"),
+        ],
+        includes: &[],
+    },
+    Group {
+        name: "pods",
+        routes: &[
+            ("GET", "/libpod/pods/json"),
+            ("GET", "/libpod/pods/stats"),
+            ("GET", "/libpod/pods/{id}/json"),
+            ("GET", "/libpod/pods/{id}/top"),
+            ("GET", "/libpod/pods/{id}/exists"),
+        ],
+        includes: &[],
+    },
+    Group {
+        name: "system",
+        routes: &[("GET", "/system/df"), ("GET", "/libpod/system/df")],
+        includes: &[],
+    },
+];
+
+/// Get a group by its name
+pub fn find(name: &str) -> Option<&'static Group> {
+    GROUPS.iter().find(|group| group.name == name)
+}
+
+impl Group {
+    /// Get the routes of the group and of the groups it includes
+    pub fn all_routes(&self) -> Vec<(&'static str, &'static str)> {
+        let mut routes = self.routes.to_vec();
+        for group in self.includes.iter().filter_map(|name| find(name)) {
+            routes.extend(group.all_routes());
+        }
+        routes
+    }
+}
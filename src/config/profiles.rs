@@ -0,0 +1,312 @@
+/// A policy bundled with the binary for a well-known consumer of the API
+pub struct Profile {
+    pub name: &'static str,
+    pub version: u32,
+    pub source: &'static str,
+}
+
+pub const PROFILES: &[Profile] = &[
+    Profile {
+        name: "traefik",
+        version: 1,
+        source: include_str!("profiles/traefik-1.toml"),
+    },
+    Profile {
+        name: "watchtower",
+        version: 1,
+        source: include_str!("profiles/watchtower-1.toml"),
+    },
+    Profile {
+        name: "portainer",
+        version: 1,
+        source: include_str!("profiles/portainer-1.toml"),
+    },
+    Profile {
+        name: "prometheus",
+        version: 1,
+        source: include_str!("profiles/prometheus-1.toml"),
+    },
+];
+
+/// Get a profile from its name, `traefik@1` for a given version or `traefik` for the latest one
+pub fn find(reference: &str) -> Option<&'static Profile> {
+    let (name, version) = match reference.split_once('@') {
+        Some((name, version)) => (name, Some(version.parse::<u32>().ok()?)),
+        None => (reference, None),
+    };
+
+    PROFILES
+        .iter()
+        .filter(|profile| profile.name == name)
+        .filter(|profile| version.is_none_or(|version| profile.version == version))
+        .max_by_key(|profile| profile.version)
+}
+
+/// Merge the local config over a profile
+///
+/// Tables are merged recursively, values of the local config replace those of
+/// the profile and arrays are concatenated, local entries first so that local
+/// rules are evaluated before the rules of the profile.
+pub fn merge(profile: toml::Value, local: toml::Value) -> toml::Value {
+    match (profile, local) {
+        (toml::Value::Table(mut profile), toml::Value::Table(local)) => {
+            for (key, value) in local {
+                let merged = match profile.remove(&key) {
+                    Some(base) => merge(base, value),
+                    None => value,
+                };
+                profile.insert(key, merged);
+            }
+            toml::Value::Table(profile)
+        }
+        (toml::Value::Array(profile), toml::Value::Array(mut local)) => {
+            local.extend(profile);
+            toml::Value::Array(local)
+        }
+        (_, local) => local,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::watch;
+
+    use crate::config::{compile_policy, get_profile_config};
+    use crate::filter::{FilterResult, FiltersHandler};
+
+    /// The filters of a bundled profile, without a local config
+    fn filters(profile: &str) -> FiltersHandler {
+        let config = get_profile_config(profile).expect("invalid profile");
+        let (_, policy) = watch::channel(compile_policy(&config).expect("invalid policy"));
+        FiltersHandler::new(policy)
+    }
+
+    /// Decide a request as the proxy does, its body included
+    fn decide(filters: &FiltersHandler, method: &str, target: &str, body: &str) -> FilterResult {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            target,
+            body.len(),
+            body
+        );
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut req = httparse::Request::new(&mut headers);
+        let header_size = match req.parse(raw.as_bytes()) {
+            Ok(httparse::Status::Complete(header_size)) => header_size,
+            _ => panic!("invalid request {} {}", method, target),
+        };
+
+        match filters.is_action_allowed(&req, req.headers) {
+            verdict if verdict.result.is_allowed() => {
+                filters
+                    .is_body_allowed(&req, &raw.as_bytes()[header_size..])
+                    .result
+            }
+            verdict => verdict.result,
+        }
+    }
+
+    fn assert_allowed(filters: &FiltersHandler, requests: &[(&str, &str, &str)]) {
+        for (method, target, body) in requests {
+            let result = decide(filters, method, target, body);
+            assert!(result.is_allowed(), "{} {} is {}", method, target, result);
+        }
+    }
+
+    fn assert_denied(filters: &FiltersHandler, requests: &[(&str, &str, &str)]) {
+        for (method, target, body) in requests {
+            let result = decide(filters, method, target, body);
+            assert!(!result.is_allowed(), "{} {} is allowed", method, target);
+        }
+    }
+
+    #[test]
+    fn traefik() {
+        let filters = filters("traefik");
+
+        assert_allowed(
+            &filters,
+            &[
+                ("HEAD", "/_ping", ""),
+                ("GET", "/_ping", ""),
+                ("GET", "/v1.24/version", ""),
+                ("GET", "/v1.24/containers/json?limit=0", ""),
+                ("GET", "/v1.24/containers/4c01db0b339c/json", ""),
+                (
+                    "GET",
+                    "/v1.24/events?filters=%7B%22type%22%3A%7B%22container%22%3Atrue%7D%7D",
+                    "",
+                ),
+            ],
+        );
+        assert_denied(
+            &filters,
+            &[
+                ("GET", "/v1.24/info", ""),
+                ("GET", "/v1.24/containers/4c01db0b339c/logs", ""),
+                ("GET", "/v1.24/images/json", ""),
+                ("POST", "/v1.24/containers/4c01db0b339c/start", ""),
+                ("POST", "/v1.24/containers/create", r#"{"Image":"nginx"}"#),
+                (
+                    "POST",
+                    "/v1.24/containers/4c01db0b339c/exec",
+                    r#"{"Cmd":["sh"]}"#,
+                ),
+                ("GET", "/libpod/containers/json", ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn watchtower() {
+        let filters = filters("watchtower");
+
+        assert_allowed(
+            &filters,
+            &[
+                ("GET", "/_ping", ""),
+                ("GET", "/v1.25/version", ""),
+                ("GET", "/v1.25/containers/json?filters=%7B%7D", ""),
+                ("GET", "/v1.25/containers/4c01db0b339c/json", ""),
+                ("GET", "/v1.25/images/sha256:1a2b3c/json", ""),
+                (
+                    "GET",
+                    "/v1.25/distribution/docker.io/library/nginx:latest/json",
+                    "",
+                ),
+                (
+                    "POST",
+                    "/v1.25/images/create?fromImage=nginx&tag=latest",
+                    "",
+                ),
+                ("POST", "/v1.25/containers/4c01db0b339c/stop?t=10", ""),
+                ("DELETE", "/v1.25/containers/4c01db0b339c?v=false", ""),
+                (
+                    "POST",
+                    "/v1.25/containers/create?name=web",
+                    r#"{"Image":"nginx:latest","HostConfig":{}}"#,
+                ),
+                (
+                    "POST",
+                    "/v1.25/networks/web/connect",
+                    r#"{"Container":"5d12"}"#,
+                ),
+                ("POST", "/v1.25/containers/5d12/start", ""),
+                ("DELETE", "/v1.25/images/sha256:1a2b3c", ""),
+            ],
+        );
+        assert_denied(
+            &filters,
+            &[
+                ("POST", "/v1.25/containers/5d12/exec", r#"{"Cmd":["sh"]}"#),
+                ("POST", "/v1.25/build", ""),
+                ("GET", "/v1.25/containers/5d12/logs", ""),
+                (
+                    "POST",
+                    "/v1.25/containers/create",
+                    r#"{"Image":"nginx","HostConfig":{"Privileged":true}}"#,
+                ),
+                (
+                    "POST",
+                    "/v1.25/containers/create",
+                    r#"{"Image":"nginx","HostConfig":{"Binds":["/var/run/docker.sock:/var/run/docker.sock"]}}"#,
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn portainer() {
+        let filters = filters("portainer");
+
+        assert_allowed(
+            &filters,
+            &[
+                ("GET", "/_ping", ""),
+                ("GET", "/v1.41/info", ""),
+                ("GET", "/v1.41/containers/json?all=1", ""),
+                ("GET", "/v1.41/images/json", ""),
+                ("GET", "/v1.41/volumes", ""),
+                ("GET", "/v1.41/networks", ""),
+                (
+                    "POST",
+                    "/v1.41/images/create?fromImage=nginx&tag=latest",
+                    "",
+                ),
+                ("POST", "/v1.41/volumes/create", r#"{"Name":"data"}"#),
+                (
+                    "POST",
+                    "/v1.41/containers/create?name=web",
+                    r#"{"Image":"nginx:latest","HostConfig":{"Binds":["data:/data"]}}"#,
+                ),
+                ("POST", "/v1.41/containers/5d12/start", ""),
+                ("GET", "/v1.41/containers/5d12/archive?path=/etc", ""),
+                ("POST", "/v1.41/containers/5d12/exec", r#"{"Cmd":["sh"]}"#),
+                ("POST", "/v1.41/exec/8e21/start", r#"{"Tty":true}"#),
+                ("POST", "/v1.41/build?t=app", ""),
+                ("DELETE", "/v1.41/containers/5d12?force=true", ""),
+                ("DELETE", "/v1.41/networks/web", ""),
+            ],
+        );
+        assert_denied(
+            &filters,
+            &[
+                (
+                    "POST",
+                    "/v1.41/containers/create",
+                    r#"{"Image":"nginx","HostConfig":{"Privileged":true}}"#,
+                ),
+                (
+                    "POST",
+                    "/v1.41/containers/create",
+                    r#"{"Image":"nginx","HostConfig":{"Binds":["/:/host"]}}"#,
+                ),
+                (
+                    "POST",
+                    "/v1.41/volumes/create",
+                    r#"{"Name":"host","DriverOpts":{"type":"none","o":"bind","device":"/"}}"#,
+                ),
+                ("PUT", "/v1.41/containers/5d12/archive?path=/etc", ""),
+                ("POST", "/v1.41/containers/prune", ""),
+                ("POST", "/v1.41/secrets/create", r#"{"Name":"key"}"#),
+            ],
+        );
+    }
+
+    #[test]
+    fn prometheus() {
+        let filters = filters("prometheus");
+
+        assert_allowed(
+            &filters,
+            &[
+                ("GET", "/_ping", ""),
+                ("GET", "/v4.0.0/libpod/info", ""),
+                ("GET", "/v4.0.0/libpod/containers/json?all=true", ""),
+                ("GET", "/v4.0.0/libpod/containers/stats?stream=false", ""),
+                ("GET", "/v4.0.0/libpod/pods/json", ""),
+                ("GET", "/v4.0.0/libpod/images/json", ""),
+                ("GET", "/v4.0.0/libpod/networks/json", ""),
+                ("GET", "/v4.0.0/libpod/volumes/json", ""),
+                ("GET", "/v4.0.0/libpod/system/df", ""),
+                ("GET", "/v4.0.0/libpod/events?stream=true", ""),
+            ],
+        );
+        assert_denied(
+            &filters,
+            &[
+                ("GET", "/v4.0.0/libpod/containers/5d12/logs", ""),
+                ("POST", "/v4.0.0/libpod/containers/5d12/start", ""),
+                (
+                    "POST",
+                    "/v4.0.0/libpod/containers/5d12/exec",
+                    r#"{"Cmd":["sh"]}"#,
+                ),
+                ("POST", "/v4.0.0/libpod/images/pull?reference=nginx", ""),
+                ("DELETE", "/v4.0.0/libpod/containers/5d12", ""),
+                ("POST", "/v4.0.0/libpod/system/prune", ""),
+            ],
+        );
+    }
+}
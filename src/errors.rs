@@ -12,6 +12,30 @@ pub enum ConnectPodmanError {
 pub enum OpenInetError {
     #[error("failed to open socket")]
     SocketError(#[from] std::io::Error),
+    #[error("invalid address {0}, expected an IP address and a port")]
+    InvalidAddress(String),
+    #[error("failed to set up TLS")]
+    TlsError(#[from] TlsError),
+}
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("failed to read {0}")]
+    PemError(String, #[source] rustls::pki_types::pem::Error),
+    #[error("no certificate found in {0}")]
+    NoCertificate(String),
+    #[error("set both the certificate and the private key")]
+    MissingKeyPair(),
+    #[error("client verification requires a CA certificate")]
+    MissingCaCertificate(),
+    #[error("invalid CA certificate in {0}")]
+    CaCertificateError(String, #[source] rustls::Error),
+    #[error("invalid TLS configuration")]
+    ConfigError(#[from] rustls::Error),
+    #[error("invalid CA certificates")]
+    VerifierError(#[from] rustls::server::VerifierBuilderError),
+    #[error("failed to handle SIGHUP")]
+    SignalError(#[from] std::io::Error),
 }
 
 #[derive(Error, Debug)]
@@ -20,6 +44,14 @@ pub enum OpenUnixSocketError {
     SocketError(#[from] std::io::Error),
     #[error("socket file already exists")]
     SocketExists(),
+    #[error("failed to set the socket owner")]
+    OwnerError(#[from] nix::Error),
+    #[error("invalid socket mode {0}, expected an octal mode such as 0660")]
+    InvalidMode(String),
+    #[error("unknown socket owner {0}")]
+    UnknownOwner(String),
+    #[error("unknown socket group {0}")]
+    UnknownGroup(String),
 }
 
 #[derive(Error, Debug)]
@@ -32,4 +64,28 @@ pub enum ReadCompleteError {
     ParseError(#[from] httparse::Error),
     #[error("buffer capacity exceeded")]
     ExceededMaxSize(),
+    #[error("invalid Content-Length header")]
+    InvalidContentLength(),
+    #[error("conflicting Content-Length and Transfer-Encoding headers")]
+    ConflictingFraming(),
+    #[error("unsupported Transfer-Encoding header")]
+    UnsupportedTransferEncoding(),
+    #[error("invalid chunked body")]
+    InvalidChunk(),
+}
+
+#[derive(Error, Debug)]
+pub enum SystemdError {
+    #[error("failed to read the sockets passed by systemd")]
+    ListenFdsError(#[from] std::io::Error),
+    #[error("failed to inspect the socket passed by systemd")]
+    SocketError(#[from] nix::Error),
+    #[error("no socket passed by systemd, LISTEN_FDS is not set")]
+    NoSocket(),
+    #[error("{0} sockets passed by systemd, select one with --name")]
+    SeveralSockets(usize),
+    #[error("no socket named {0} passed by systemd")]
+    SocketNotFound(String),
+    #[error("socket {0} is neither a Unix nor a TCP socket")]
+    UnsupportedSocket(String),
 }
@@ -0,0 +1,91 @@
+use anyhow::{bail, Context};
+use std::fs;
+
+use crate::cli::ExplainArgs;
+use crate::filter::{FilterResult, FiltersHandler};
+use crate::path;
+
+/// Build the raw request a client would send for the arguments
+fn build_request(args: &ExplainArgs) -> anyhow::Result<Vec<u8>> {
+    let body = match &args.body {
+        Some(body_path) => fs::read(body_path)
+            .with_context(|| format!("Failed to read the body file {}", body_path))?,
+        None => Vec::new(),
+    };
+
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\n",
+        args.method.to_uppercase(),
+        args.target
+    );
+    for header in &args.headers {
+        match header.split_once(':') {
+            Some((name, value)) => head.push_str(&format!("{}: {}\r\n", name.trim(), value.trim())),
+            None => bail!("Invalid header {}, expected Name: value", header),
+        }
+    }
+    if !body.is_empty() {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+
+    let mut raw = head.into_bytes();
+    raw.extend_from_slice(&body);
+    Ok(raw)
+}
+
+/// Describe a filter result as the response the client would get
+fn describe(result: &FilterResult) -> String {
+    match result {
+        FilterResult::Allowed => String::from("allowed, forwarded to Podman"),
+        FilterResult::MethodNotAllowed => String::from("denied, 405 Method Not Allowed"),
+        FilterResult::Forbidden => String::from("denied, 403 Forbidden"),
+        FilterResult::BadRequest => String::from("denied, 400 Bad Request"),
+        FilterResult::Violation(reason) => format!("denied, 403 Forbidden: {}", reason),
+    }
+}
+
+/// Evaluate a sample request against the policy and print how it is decided
+///
+/// Returns `true` if the request would be forwarded to Podman.
+pub fn explain(filters_handler: &FiltersHandler, args: &ExplainArgs) -> anyhow::Result<bool> {
+    let raw = build_request(args)?;
+
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut req = httparse::Request::new(&mut headers);
+    let header_size = match req.parse(&raw).context("Invalid request")? {
+        httparse::Status::Complete(header_size) => header_size,
+        httparse::Status::Partial => bail!("Incomplete request"),
+    };
+
+    println!("Request:    {} {}", args.method.to_uppercase(), args.target);
+    match path::normalize(&args.target) {
+        Ok(path) => {
+            println!("Normalized: {}", path.target());
+            println!("Canonical:  {}", path.canonical);
+            match &path.version {
+                Some(version) => println!("Version:    {}", version),
+                None => println!("Version:    none"),
+            }
+        }
+        Err(e) => println!("Normalized: invalid, {}", e),
+    }
+
+    let verdict = match filters_handler.is_action_allowed(&req, req.headers) {
+        verdict if verdict.result.is_allowed() => {
+            verdict.and(filters_handler.is_body_allowed(&req, &raw[header_size..]))
+        }
+        verdict => verdict,
+    };
+
+    println!("Rule:       {}", verdict.rule.as_deref().unwrap_or("none"));
+    println!("Decision:   {}", describe(&verdict.result));
+    if let Some((audited, audited_rule)) = &verdict.audited {
+        match audited_rule {
+            Some(rule) => println!("Audited:    {}, rule {}", describe(audited), rule),
+            None => println!("Audited:    {}", describe(audited)),
+        }
+    }
+
+    Ok(verdict.result.is_allowed())
+}
@@ -1,6 +1,13 @@
-use crate::config;
+use std::sync::Arc;
+use tokio::sync::watch;
 
-use config::Filters;
+use crate::config::{Mode, RuleAction};
+use crate::images::{self, PullApi, ShortNames};
+use crate::inspect;
+use crate::learn::Learner;
+use crate::path;
+use crate::policy::Policy;
+use crate::query;
 use httparse::Request;
 
 pub enum FilterResult {
@@ -8,57 +15,313 @@ pub enum FilterResult {
     MethodNotAllowed,
     Forbidden,
     BadRequest,
+    /// Forbidden, with the reason given to the client
+    Violation(String),
 }
 
+impl std::fmt::Display for FilterResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FilterResult::Allowed => write!(f, "allowed"),
+            FilterResult::MethodNotAllowed => write!(f, "method not allowed"),
+            FilterResult::Forbidden => write!(f, "forbidden"),
+            FilterResult::BadRequest => write!(f, "bad request"),
+            FilterResult::Violation(reason) => write!(f, "forbidden, {}", reason),
+        }
+    }
+}
+
+impl FilterResult {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, FilterResult::Allowed)
+    }
+
+    /// The reason given to the client, for violations
+    pub fn reason(&self) -> Option<String> {
+        match self {
+            FilterResult::Violation(reason) => Some(reason.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// The decision of the filters on a request
+pub struct Verdict {
+    /// The result enforced
+    pub result: FilterResult,
+    /// The rule that decided, if any
+    pub rule: Option<String>,
+    /// The result, and its rule, if the decisions in audit mode were enforced,
+    /// when the request would be decided differently
+    pub audited: Option<(FilterResult, Option<String>)>,
+}
+
+impl Verdict {
+    fn enforced(result: FilterResult, rule: Option<String>) -> Self {
+        Verdict {
+            result,
+            rule,
+            audited: None,
+        }
+    }
+
+    /// Combine with the verdict on the body of a request allowed by this verdict
+    ///
+    /// The decisions in audit mode are only kept if they would change the result.
+    pub fn and(self, body: Verdict) -> Verdict {
+        let allowed = body.result.is_allowed();
+        let audited = self
+            .audited
+            .or(body.audited)
+            .filter(|(audited, _)| audited.is_allowed() != allowed);
+
+        Verdict {
+            result: body.result,
+            rule: self.rule,
+            audited,
+        }
+    }
+}
+
+/// Applies the current policy, which is replaced when the config is reloaded
 #[derive(Clone)]
 pub struct FiltersHandler {
-    filters: Filters,
+    policy: watch::Receiver<Arc<Policy>>,
+    /// Records the requests when learning a policy
+    learner: Option<Arc<Learner>>,
 }
 
 impl FiltersHandler {
-    pub fn new(filters: Filters) -> Self {
-        FiltersHandler { filters }
+    pub fn new(policy: watch::Receiver<Arc<Policy>>) -> Self {
+        FiltersHandler {
+            policy,
+            learner: None,
+        }
     }
 
-    pub fn is_action_allowed(self, req: &Request, headers: &[httparse::Header]) -> FilterResult {
+    /// Record every valid request to the learner
+    pub fn with_learner(mut self, learner: Arc<Learner>) -> Self {
+        self.learner = Some(learner);
+        self
+    }
+
+    /// Get the current policy, kept for the whole evaluation of a request
+    fn policy(&self) -> Arc<Policy> {
+        self.policy.borrow().clone()
+    }
+
+    /// Evaluate the rules of the request method in order, the first matching rule decides
+    ///
+    /// A request matching no rule is forbidden. Malformed requests are rejected
+    /// even in audit mode.
+    pub fn is_action_allowed(&self, req: &Request, headers: &[httparse::Header]) -> Verdict {
         if Self::is_headers_forbidden(headers) {
-            return FilterResult::Forbidden;
+            return Verdict::enforced(FilterResult::Forbidden, None);
         }
 
         let method = match req.method {
             Some(method) => method,
-            None => return FilterResult::MethodNotAllowed,
+            None => return Verdict::enforced(FilterResult::MethodNotAllowed, None),
+        };
+
+        let target = match req.path {
+            Some(target) => target,
+            None => return Verdict::enforced(FilterResult::Forbidden, None),
         };
 
-        let path = match req.path {
-            Some(path) => path,
-            None => return FilterResult::Forbidden,
+        let path = match path::normalize(target) {
+            Ok(path) => path,
+            Err(_) => return Verdict::enforced(FilterResult::BadRequest, None),
         };
+        let params = query::parse_query(&path.query);
 
-        let proxy = match method {
-            "GET" => &self.filters.get,
-            "HEAD" => &self.filters.head,
-            "POST" => &self.filters.post,
-            "PUT" => &self.filters.put,
-            "PATCH" => &self.filters.patch,
-            "DELETE" => &self.filters.delete,
-            _ => return FilterResult::BadRequest,
+        let policy = self.policy();
+        let method_policy = match policy.method(method) {
+            Some(method_policy) => method_policy,
+            None => return Verdict::enforced(FilterResult::BadRequest, None),
         };
 
-        if proxy.allowed {
-            let reg = match regex::Regex::new(&proxy.regex) {
-                Ok(regex) => regex,
-                Err(_) => {
-                    panic!("Invalid regex syntax: {}", &proxy.regex)
+        if let Some(learner) = &self.learner {
+            learner.record(method, &path.canonical);
+        }
+
+        let decide =
+            |include_audit: bool| match method_policy.first_match(&path, &params, include_audit) {
+                Some(rule) => {
+                    log::debug!("Request matched rule '{}'", rule.name);
+
+                    let result = match rule.action {
+                        RuleAction::Allow => {
+                            Self::is_pull_allowed(&policy, &path.canonical, &params)
+                        }
+                        RuleAction::Deny => FilterResult::Forbidden,
+                    };
+                    (result, Some(rule.name.clone()))
                 }
+                None => (FilterResult::Forbidden, None),
             };
 
-            if reg.is_match(path) {
-                return FilterResult::Allowed;
+        let (result, rule) = decide(true);
+        if !policy.audits() {
+            return Verdict::enforced(result, rule);
+        }
+
+        let enforced = match policy.mode {
+            Mode::Audit => (FilterResult::Allowed, rule.clone()),
+            Mode::Enforce => decide(false),
+        };
+
+        match result.is_allowed() == enforced.0.is_allowed() {
+            true => Verdict::enforced(enforced.0, enforced.1),
+            false => Verdict {
+                result: enforced.0,
+                rule: enforced.1,
+                audited: Some((result, rule)),
+            },
+        }
+    }
+
+    /// Check if the body of a request is inspected, and must be read before deciding
+    pub fn inspects_body(req: &Request) -> bool {
+        req.method == Some("POST")
+            && req
+                .path
+                .and_then(|target| path::normalize(target).ok())
+                .is_some_and(|path| {
+                    inspect::container_create_api(&path.canonical).is_some()
+                        || inspect::is_volume_create(&path.canonical)
+                })
+    }
+
+    /// Inspect the body of the requests creating containers and volumes
+    ///
+    /// Requests to other endpoints are always allowed. Bodies which are not
+    /// valid JSON are rejected even in audit mode.
+    pub fn is_body_allowed(&self, req: &Request, body: &[u8]) -> Verdict {
+        let path = match req.path.map(path::normalize) {
+            Some(Ok(path)) => path,
+            _ => return Verdict::enforced(FilterResult::BadRequest, None),
+        };
+
+        if !Self::inspects_body(req) {
+            return Verdict::enforced(FilterResult::Allowed, None);
+        }
+
+        let body: serde_json::Value = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(_) => return Verdict::enforced(FilterResult::BadRequest, None),
+        };
+
+        let policy = self.policy();
+        let result = match inspect::container_create_api(&path.canonical) {
+            Some(api) => Self::inspect_body(&policy, api, &body),
+            None => match inspect::inspect_volume_create(&policy.container_create, &body) {
+                Ok(()) => FilterResult::Allowed,
+                Err(reason) => FilterResult::Violation(reason),
+            },
+        };
+
+        match policy.mode {
+            Mode::Audit if !result.is_allowed() => Verdict {
+                result: FilterResult::Allowed,
+                rule: None,
+                audited: Some((result, None)),
+            },
+            _ => Verdict::enforced(result, None),
+        }
+    }
+
+    fn inspect_body(
+        policy: &Policy,
+        api: inspect::CreateApi,
+        body: &serde_json::Value,
+    ) -> FilterResult {
+        if let Err(reason) = inspect::inspect_container_create(&policy.container_create, api, body)
+        {
+            return FilterResult::Violation(reason);
+        }
+
+        let image_policy = match &policy.images {
+            Some(image_policy) => image_policy,
+            None => return FilterResult::Allowed,
+        };
+
+        let (images, rootfs) = inspect::container_create_sources(api, body);
+        if !rootfs.is_empty() {
+            return FilterResult::Violation(String::from("rootfs is not allowed"));
+        }
+
+        let short_names = match api {
+            inspect::CreateApi::Compat => ShortNames::DockerHub,
+            inspect::CreateApi::Libpod => ShortNames::SearchRegistries,
+        };
+        for image in images {
+            if let Err(reason) = image_policy.check(image, short_names, false, true) {
+                return FilterResult::Violation(reason);
             }
         }
 
-        return FilterResult::Forbidden;
+        FilterResult::Allowed
+    }
+
+    /// Check the images pulled by a request against the images allowlist
+    ///
+    /// Requests to other endpoints are always allowed.
+    fn is_pull_allowed(
+        policy: &Policy,
+        canonical: &str,
+        params: &[(String, String)],
+    ) -> FilterResult {
+        let image_policy = match &policy.images {
+            Some(image_policy) => image_policy,
+            None => return FilterResult::Allowed,
+        };
+
+        let api = match images::pull_api(canonical) {
+            Some(api) => api,
+            None => return FilterResult::Allowed,
+        };
+
+        let references: Vec<String> = match api {
+            PullApi::Compat => {
+                if query::values(params, "fromSrc").next().is_some() {
+                    return FilterResult::Violation(String::from("fromSrc is not allowed"));
+                }
+
+                // Podman appends the tag or digest to the image name as is
+                let tags: Vec<&str> = query::values(params, "tag")
+                    .filter(|tag| !tag.is_empty())
+                    .collect();
+
+                query::values(params, "fromImage")
+                    .flat_map(|image| match tags.is_empty() {
+                        true => vec![image.to_string()],
+                        false => tags
+                            .iter()
+                            .map(|tag| match tag.starts_with("sha256:") {
+                                true => format!("{}@{}", image, tag),
+                                false => format!("{}:{}", image, tag),
+                            })
+                            .collect(),
+                    })
+                    .collect()
+            }
+            PullApi::Libpod => query::values(params, "reference")
+                .map(String::from)
+                .collect(),
+        };
+
+        // The libpod endpoint pulls every tag of the repository with allTags
+        let all_tags = api == PullApi::Libpod && query::is_true(params, "allTags");
+
+        for reference in references {
+            if let Err(reason) = image_policy.check(&reference, api.short_names(), all_tags, false)
+            {
+                return FilterResult::Violation(reason);
+            }
+        }
+
+        FilterResult::Allowed
     }
 
     fn is_headers_forbidden(headers: &[httparse::Header]) -> bool {
@@ -71,3 +334,118 @@ impl FiltersHandler {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audited(result: FilterResult, rule: &str) -> Verdict {
+        Verdict {
+            result: FilterResult::Allowed,
+            rule: Some(String::from("create")),
+            audited: Some((result, Some(String::from(rule)))),
+        }
+    }
+
+    #[test]
+    fn and_keeps_the_rule_of_the_path() {
+        let verdict = Verdict::enforced(FilterResult::Allowed, Some(String::from("create"))).and(
+            Verdict::enforced(
+                FilterResult::Violation(String::from("privileged is not allowed")),
+                None,
+            ),
+        );
+
+        assert_eq!(verdict.rule.as_deref(), Some("create"));
+        assert_eq!(
+            verdict.result.reason().as_deref(),
+            Some("privileged is not allowed")
+        );
+        assert!(verdict.audited.is_none());
+    }
+
+    #[test]
+    fn and_drops_audited_decisions_matching_the_result() {
+        let verdict = audited(FilterResult::Forbidden, "audit-no-create").and(Verdict::enforced(
+            FilterResult::Violation(String::from("privileged is not allowed")),
+            None,
+        ));
+
+        assert!(!verdict.result.is_allowed());
+        assert_eq!(
+            verdict.result.reason().as_deref(),
+            Some("privileged is not allowed")
+        );
+        assert!(verdict.audited.is_none());
+    }
+
+    #[test]
+    fn and_keeps_audited_decisions_changing_the_result() {
+        let verdict = audited(FilterResult::Forbidden, "audit-no-create")
+            .and(Verdict::enforced(FilterResult::Allowed, None));
+
+        assert!(verdict.result.is_allowed());
+        let (result, rule) = verdict.audited.unwrap();
+        assert!(!result.is_allowed());
+        assert_eq!(rule.as_deref(), Some("audit-no-create"));
+
+        let body = Verdict {
+            result: FilterResult::Allowed,
+            rule: None,
+            audited: Some((
+                FilterResult::Violation(String::from("privileged is not allowed")),
+                None,
+            )),
+        };
+        let verdict =
+            Verdict::enforced(FilterResult::Allowed, Some(String::from("create"))).and(body);
+
+        let (result, _) = verdict.audited.unwrap();
+        assert_eq!(
+            result.reason().as_deref(),
+            Some("privileged is not allowed")
+        );
+    }
+
+    #[test]
+    fn pulls_append_the_tag_to_the_image() {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [[images.allowed]]
+            registry = "docker.io"
+            repository = "library/alpine"
+            tag = "3.*"
+            "#,
+        )
+        .unwrap();
+        let policy = crate::config::compile_policy(&config).unwrap();
+        let pull = |params: &[(&str, &str)]| {
+            let params: Vec<(String, String)> = params
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            FiltersHandler::is_pull_allowed(&policy, "/images/create", &params)
+        };
+        let digest = format!("sha256:{}", "a".repeat(64));
+
+        assert!(pull(&[("fromImage", "alpine"), ("tag", "3.20")]).is_allowed());
+        assert!(pull(&[("fromImage", "alpine:3.20"), ("tag", "")]).is_allowed());
+        assert_eq!(
+            pull(&[("fromImage", "alpine"), ("tag", "edge")])
+                .reason()
+                .as_deref(),
+            Some("image docker.io/library/alpine:edge is not allowed")
+        );
+        // Every tag given is pulled, not only the last one
+        assert!(!pull(&[("fromImage", "alpine"), ("tag", "3.20"), ("tag", "edge")]).is_allowed());
+        // A tag appended to a tagged name makes an invalid reference
+        assert!(!pull(&[("fromImage", "alpine:3.20"), ("tag", "edge")]).is_allowed());
+        assert_eq!(
+            pull(&[("fromImage", "alpine"), ("tag", &digest)])
+                .reason()
+                .as_deref(),
+            Some(format!("image docker.io/library/alpine@{} is not allowed", digest).as_str())
+        );
+        assert!(!pull(&[("fromImage", "alpine"), ("fromSrc", "-")]).is_allowed());
+    }
+}
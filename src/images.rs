@@ -0,0 +1,562 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::config::{AllowedImage, Images};
+
+const DEFAULT_REGISTRY: &str = "docker.io";
+const DEFAULT_TAG: &str = "latest";
+
+/// Transports accepted by Podman in place of a registry reference
+const TRANSPORTS: [&str; 9] = [
+    "containers-storage:",
+    "dir:",
+    "docker-archive:",
+    "docker-daemon:",
+    "oci:",
+    "oci-archive:",
+    "ostree:",
+    "sif:",
+    "tarball:",
+];
+
+lazy_static! {
+    static ref IMAGES_CREATE_PATH: Regex = Regex::new(r"^/images/create/?$").unwrap();
+    static ref LIBPOD_PULL_PATH: Regex = Regex::new(r"^/libpod/images/pull/?$").unwrap();
+    static ref IMAGE_ID: Regex = Regex::new(r"^(?:sha256:)?[a-f0-9]{12,64}$").unwrap();
+    static ref REGISTRY: Regex = Regex::new(r"^[a-zA-Z0-9.\-]+(?::[0-9]+)?$").unwrap();
+    static ref REPOSITORY_COMPONENT: Regex =
+        Regex::new(r"^[a-z0-9]+(?:(?:[._]|__|-+)[a-z0-9]+)*$").unwrap();
+    static ref TAG: Regex = Regex::new(r"^[\w][\w.\-]{0,127}$").unwrap();
+    static ref DIGEST: Regex =
+        Regex::new(r"^[a-z0-9]+(?:[.+_\-][a-z0-9]+)*:[a-zA-Z0-9=_\-]+$").unwrap();
+}
+
+/// A fully qualified image reference
+pub struct ImageReference {
+    /// Whether the reference named its registry, rather than being a short name
+    pub qualified: bool,
+    pub registry: String,
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl ImageReference {
+    /// Parse and normalize an image reference the way Docker does
+    ///
+    /// Short names are resolved against Docker Hub. References using another
+    /// transport than `docker://` are rejected.
+    pub fn parse(reference: &str) -> Option<Self> {
+        let lowercase = reference.to_lowercase();
+        if TRANSPORTS
+            .iter()
+            .any(|transport| lowercase.starts_with(transport))
+        {
+            return None;
+        }
+
+        let reference = reference.strip_prefix("docker://").unwrap_or(reference);
+
+        let (name, digest) = match reference.split_once('@') {
+            Some((name, digest)) if DIGEST.is_match(digest) => (name, Some(digest.to_string())),
+            Some(_) => return None,
+            None => (reference, None),
+        };
+
+        let last_slash = name.rfind('/').map_or(0, |index| index + 1);
+        let (name, tag) = match name[last_slash..].rfind(':') {
+            Some(index) => {
+                let tag = &name[last_slash + index + 1..];
+                if !TAG.is_match(tag) {
+                    return None;
+                }
+                (&name[..last_slash + index], Some(tag.to_string()))
+            }
+            None => (name, None),
+        };
+
+        let (qualified, registry, repository) = match name.split_once('/') {
+            Some((registry, repository))
+                if registry.contains('.') || registry.contains(':') || registry == "localhost" =>
+            {
+                (true, registry.to_string(), repository.to_string())
+            }
+            _ => (false, DEFAULT_REGISTRY.to_string(), name.to_string()),
+        };
+
+        let registry = match registry.as_str() {
+            "index.docker.io" | "registry-1.docker.io" => DEFAULT_REGISTRY.to_string(),
+            _ => registry,
+        };
+
+        let repository = if registry == DEFAULT_REGISTRY && !repository.contains('/') {
+            format!("library/{}", repository)
+        } else {
+            repository
+        };
+
+        if !REGISTRY.is_match(&registry)
+            || !repository
+                .split('/')
+                .all(|component| REPOSITORY_COMPONENT.is_match(component))
+        {
+            return None;
+        }
+
+        let tag = match (&tag, &digest) {
+            (None, None) => Some(DEFAULT_TAG.to_string()),
+            _ => tag,
+        };
+
+        Some(ImageReference {
+            qualified,
+            registry,
+            repository,
+            tag,
+            digest,
+        })
+    }
+}
+
+impl std::fmt::Display for ImageReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+/// An allowed image entry, with its patterns compiled
+struct ImageMatcher {
+    registry: Option<Regex>,
+    repository: Option<Regex>,
+    tag: Option<Regex>,
+    regex: Option<Regex>,
+}
+
+impl ImageMatcher {
+    fn compile(allowed: &AllowedImage) -> Result<Self, regex::Error> {
+        let glob = |pattern: &Option<String>| pattern.as_deref().map(glob_to_regex).transpose();
+
+        Ok(ImageMatcher {
+            registry: glob(&allowed.registry)?,
+            repository: glob(&allowed.repository)?,
+            tag: glob(&allowed.tag)?,
+            regex: allowed.regex.as_deref().map(Regex::new).transpose()?,
+        })
+    }
+
+    fn is_match(&self, image: &ImageReference) -> bool {
+        let matches = |pattern: &Option<Regex>, value: &str| {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(value))
+        };
+
+        matches(&self.registry, &image.registry)
+            && matches(&self.repository, &image.repository)
+            && image.tag.iter().all(|tag| matches(&self.tag, tag))
+            && image.digest.iter().all(|digest| matches(&self.tag, digest))
+            && matches(&self.regex, &image.to_string())
+    }
+
+    /// Check if every tag of the repository of an image is allowed
+    fn is_match_all_tags(&self, image: &ImageReference) -> bool {
+        let matches = |pattern: &Option<Regex>, value: &str| {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(value))
+        };
+
+        self.tag.is_none()
+            && self.regex.is_none()
+            && matches(&self.registry, &image.registry)
+            && matches(&self.repository, &image.repository)
+    }
+}
+
+/// The compiled `[images]` allowlist
+pub struct ImagePolicy {
+    allow_ids: bool,
+    allowed: Vec<ImageMatcher>,
+}
+
+impl ImagePolicy {
+    /// Compile the patterns of every allowed image
+    ///
+    /// # Errors
+    ///
+    /// The index of the entry is returned with the error of its first invalid pattern.
+    pub fn compile(images: &Images) -> Result<Self, (usize, regex::Error)> {
+        let allowed = images
+            .allowed
+            .iter()
+            .enumerate()
+            .map(|(index, allowed)| ImageMatcher::compile(allowed).map_err(|e| (index, e)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(ImagePolicy {
+            allow_ids: images.allow_ids,
+            allowed,
+        })
+    }
+
+    /// Get every allowed image entry whose patterns do not compile, by index
+    pub fn errors(images: &Images) -> Vec<(usize, regex::Error)> {
+        images
+            .allowed
+            .iter()
+            .enumerate()
+            .filter_map(|(index, allowed)| ImageMatcher::compile(allowed).err().map(|e| (index, e)))
+            .collect()
+    }
+
+    /// Check if an image reference is allowed
+    ///
+    /// Short names are only allowed where Podman resolves them against Docker
+    /// Hub, as the proxy does. With `all_tags`, every tag of the repository is
+    /// pulled, which only entries without a `tag` nor a `regex` pattern allow.
+    /// With `ids`, the reference may be the ID of a local image, as for
+    /// container creation; pulls take every reference as a name.
+    ///
+    /// # Errors
+    ///
+    /// The reason naming the image is returned if it is not allowed.
+    pub fn check(
+        &self,
+        reference: &str,
+        short_names: ShortNames,
+        all_tags: bool,
+        ids: bool,
+    ) -> Result<(), String> {
+        if ids && IMAGE_ID.is_match(reference) {
+            return match self.allow_ids {
+                true => Ok(()),
+                false => Err(format!("image ID {} is not allowed", reference)),
+            };
+        }
+
+        let image = match ImageReference::parse(reference) {
+            Some(image) => image,
+            None => return Err(format!("image reference {} is invalid", reference)),
+        };
+
+        if !image.qualified && short_names == ShortNames::SearchRegistries {
+            return Err(format!(
+                "short name {} is not allowed, Podman resolves it through registries.conf",
+                reference
+            ));
+        }
+
+        if all_tags {
+            return match self
+                .allowed
+                .iter()
+                .any(|matcher| matcher.is_match_all_tags(&image))
+            {
+                true => Ok(()),
+                false => Err(format!(
+                    "every tag of {}/{} is not allowed",
+                    image.registry, image.repository
+                )),
+            };
+        }
+
+        if self.allowed.iter().any(|matcher| matcher.is_match(&image)) {
+            Ok(())
+        } else {
+            Err(format!("image {} is not allowed", image))
+        }
+    }
+}
+
+/// How an endpoint resolves the short names of images, e.g. `alpine`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ShortNames {
+    /// Against Docker Hub, by the Docker-compatible endpoints
+    DockerHub,
+    /// Through the search registries of `registries.conf`, by the libpod endpoints
+    SearchRegistries,
+}
+
+/// Kind of image pull endpoint targeted by a path
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PullApi {
+    /// Docker-compatible `/images/create`, with `fromImage` and `tag` parameters
+    Compat,
+    /// Podman `/libpod/images/pull`, with a `reference` parameter
+    Libpod,
+}
+
+impl PullApi {
+    /// Get how the endpoint resolves short names
+    pub fn short_names(&self) -> ShortNames {
+        match self {
+            PullApi::Compat => ShortNames::DockerHub,
+            PullApi::Libpod => ShortNames::SearchRegistries,
+        }
+    }
+}
+
+/// Get the image pull endpoint targeted by a canonical path, if any
+pub fn pull_api(canonical: &str) -> Option<PullApi> {
+    if IMAGES_CREATE_PATH.is_match(canonical) {
+        Some(PullApi::Compat)
+    } else if LIBPOD_PULL_PATH.is_match(canonical) {
+        Some(PullApi::Libpod)
+    } else {
+        None
+    }
+}
+
+/// Translate a glob into an anchored regex
+///
+/// `*` matches anything but a `/`, `**` matches anything and `?` a single character.
+fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    pattern.push('$');
+    Regex::new(&pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(reference: &str) -> Option<String> {
+        ImageReference::parse(reference).map(|image| image.to_string())
+    }
+
+    fn allowed(registry: &str, repository: &str, tag: Option<&str>) -> AllowedImage {
+        AllowedImage {
+            registry: Some(registry.to_string()),
+            repository: Some(repository.to_string()),
+            tag: tag.map(String::from),
+            regex: None,
+        }
+    }
+
+    fn policy(allow_ids: bool, allowed: Vec<AllowedImage>) -> ImagePolicy {
+        ImagePolicy::compile(&Images { allow_ids, allowed }).expect("invalid policy")
+    }
+
+    #[test]
+    fn parse_normalizes_references() {
+        let cases = [
+            ("alpine", "docker.io/library/alpine:latest"),
+            ("traefik:v3.1", "docker.io/library/traefik:v3.1"),
+            ("grafana/grafana", "docker.io/grafana/grafana:latest"),
+            (
+                "index.docker.io/library/nginx:1",
+                "docker.io/library/nginx:1",
+            ),
+            ("quay.io/podman/stable", "quay.io/podman/stable:latest"),
+            ("localhost:5000/app:dev", "localhost:5000/app:dev"),
+            ("localhost/app", "localhost/app:latest"),
+            ("docker://ghcr.io/org/app:1.0", "ghcr.io/org/app:1.0"),
+        ];
+
+        for (reference, expected) in cases {
+            assert_eq!(parse(reference).as_deref(), Some(expected), "{}", reference);
+        }
+
+        assert!(!ImageReference::parse("alpine").unwrap().qualified);
+        assert!(ImageReference::parse("docker.io/alpine").unwrap().qualified);
+    }
+
+    #[test]
+    fn parse_keeps_digests() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+
+        assert_eq!(
+            parse(&format!("alpine@{}", digest)),
+            Some(format!("docker.io/library/alpine@{}", digest))
+        );
+        assert_eq!(
+            parse(&format!("quay.io/app:1@{}", digest)),
+            Some(format!("quay.io/app:1@{}", digest))
+        );
+        assert_eq!(parse("alpine@latest"), None);
+    }
+
+    #[test]
+    fn parse_rejects_other_transports_and_invalid_names() {
+        for reference in [
+            "docker-archive:/tmp/image.tar",
+            "OCI:/srv/layout",
+            "containers-storage:alpine",
+            "dir:/tmp/image",
+            "Alpine",
+            "alpine:",
+            "quay.io/app:-tag",
+            "quay.io//app",
+        ] {
+            assert_eq!(parse(reference), None, "{}", reference);
+        }
+    }
+
+    #[test]
+    fn check_matches_globs() {
+        let policy = policy(
+            false,
+            vec![
+                allowed("docker.io", "library/traefik", Some("v3.*")),
+                allowed("quay.io", "org/**", None),
+            ],
+        );
+        let check = |reference| policy.check(reference, ShortNames::DockerHub, false, false);
+
+        assert!(check("traefik:v3.1").is_ok());
+        assert!(check("quay.io/org/team/app:1").is_ok());
+        assert_eq!(
+            check("traefik:v2.11"),
+            Err(String::from(
+                "image docker.io/library/traefik:v2.11 is not allowed"
+            ))
+        );
+        assert!(check("docker.io/library/traefik/x:v3.1").is_err());
+        assert!(check("quay.io/other/app").is_err());
+        assert_eq!(
+            check("not a reference"),
+            Err(String::from("image reference not a reference is invalid"))
+        );
+    }
+
+    #[test]
+    fn check_matches_digests_with_tag_patterns() {
+        let digest = format!("sha256:{}", "b".repeat(64));
+        let pinned = policy(
+            false,
+            vec![allowed("docker.io", "library/alpine", Some("sha256:*"))],
+        );
+        let tagged = policy(
+            false,
+            vec![allowed("docker.io", "library/alpine", Some("3.*"))],
+        );
+        let reference = format!("alpine@{}", digest);
+
+        assert!(pinned
+            .check(&reference, ShortNames::DockerHub, false, false)
+            .is_ok());
+        assert!(tagged
+            .check(&reference, ShortNames::DockerHub, false, false)
+            .is_err());
+        // Both the tag and the digest must match
+        assert!(tagged
+            .check(
+                &format!("alpine:3.20@{}", digest),
+                ShortNames::DockerHub,
+                false,
+                false
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn check_rejects_short_names_resolved_by_registries_conf() {
+        let policy = policy(false, vec![allowed("docker.io", "library/*", None)]);
+
+        assert!(policy
+            .check("alpine", ShortNames::DockerHub, false, false)
+            .is_ok());
+        assert!(policy
+            .check(
+                "docker.io/alpine",
+                ShortNames::SearchRegistries,
+                false,
+                false
+            )
+            .is_ok());
+        assert_eq!(
+            policy.check("alpine", ShortNames::SearchRegistries, false, false),
+            Err(String::from(
+                "short name alpine is not allowed, Podman resolves it through registries.conf"
+            ))
+        );
+    }
+
+    #[test]
+    fn check_all_tags() {
+        let policy = policy(
+            false,
+            vec![
+                allowed("docker.io", "library/alpine", None),
+                allowed("docker.io", "library/nginx", Some("*")),
+            ],
+        );
+        let check = |reference| policy.check(reference, ShortNames::DockerHub, true, false);
+
+        assert!(check("alpine").is_ok());
+        assert_eq!(
+            check("nginx"),
+            Err(String::from(
+                "every tag of docker.io/library/nginx is not allowed"
+            ))
+        );
+    }
+
+    #[test]
+    fn check_image_ids() {
+        let id = "0123456789ab";
+        let full_id = format!("sha256:{}", "c".repeat(64));
+        let allowing = policy(true, Vec::new());
+        let denying = policy(false, vec![allowed("**", "**", None)]);
+
+        assert!(allowing
+            .check(id, ShortNames::DockerHub, false, true)
+            .is_ok());
+        assert!(allowing
+            .check(&full_id, ShortNames::DockerHub, false, true)
+            .is_ok());
+        assert_eq!(
+            denying.check(id, ShortNames::DockerHub, false, true),
+            Err(format!("image ID {} is not allowed", id))
+        );
+        // Pulls take a hex reference as a repository name of Docker Hub
+        assert!(denying
+            .check(id, ShortNames::DockerHub, false, false)
+            .is_ok());
+        assert!(allowing
+            .check(id, ShortNames::DockerHub, false, false)
+            .is_err());
+        assert!(allowing
+            .check(&full_id, ShortNames::DockerHub, false, false)
+            .is_err());
+    }
+
+    #[test]
+    fn glob_to_regex_escapes() {
+        let glob = glob_to_regex("docker.io").unwrap();
+
+        assert!(glob.is_match("docker.io"));
+        assert!(!glob.is_match("dockerxio"));
+        assert!(glob_to_regex("a?c").unwrap().is_match("abc"));
+        assert!(!glob_to_regex("a?c").unwrap().is_match("a/c"));
+        assert!(!glob_to_regex("org/*").unwrap().is_match("org/a/b"));
+    }
+
+    #[test]
+    fn pull_apis() {
+        assert!(pull_api("/images/create") == Some(PullApi::Compat));
+        assert!(pull_api("/libpod/images/pull") == Some(PullApi::Libpod));
+        assert!(pull_api("/libpod/images/create").is_none());
+    }
+}
@@ -0,0 +1,584 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::config::ContainerCreate;
+
+lazy_static! {
+    static ref CONTAINER_CREATE_PATH: Regex =
+        Regex::new(r"^(/libpod)?/containers/create/?$").unwrap();
+    static ref VOLUME_CREATE_PATH: Regex = Regex::new(r"^(/libpod)?/volumes/create/?$").unwrap();
+}
+
+/// The flavour of a container create request body
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CreateApi {
+    /// Docker-compatible `/containers/create`, the body is a container config
+    Compat,
+    /// Podman `/libpod/containers/create`, the body is a spec generator
+    Libpod,
+}
+
+/// Get the flavour of the container create endpoint targeted by a canonical path, if any
+pub fn container_create_api(canonical: &str) -> Option<CreateApi> {
+    CONTAINER_CREATE_PATH.captures(canonical).map(|captures| {
+        if captures.get(1).is_some() {
+            CreateApi::Libpod
+        } else {
+            CreateApi::Compat
+        }
+    })
+}
+
+/// Check if a canonical path targets a volume create endpoint
+pub fn is_volume_create(canonical: &str) -> bool {
+    VOLUME_CREATE_PATH.is_match(canonical)
+}
+
+/// Check a volume create body against the allowed bind paths
+///
+/// The options of the local driver can mount any host path or device, given
+/// as `device`, when the volume is used. The options are `DriverOpts` on the
+/// Docker-compatible endpoint and `Options` on the libpod one.
+///
+/// # Errors
+///
+/// The reason naming the device is returned if it is not allowed.
+pub fn inspect_volume_create(rules: &ContainerCreate, body: &Value) -> Result<(), String> {
+    let body = match body.as_object() {
+        Some(body) => body,
+        None => return Err(String::from("body is not a JSON object")),
+    };
+
+    for options in fields(body, "DriverOpts")
+        .chain(fields(body, "Options"))
+        .filter_map(Value::as_object)
+    {
+        for device in fields(options, "device").filter_map(Value::as_str) {
+            if is_host_path(device) && !is_bind_allowed(rules, device) {
+                return Err(format!("volume device {} is not allowed", device));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check a container create body against the rules
+///
+/// Go decodes JSON keys case-insensitively, so every key matching a field
+/// regardless of its case is checked.
+///
+/// # Errors
+///
+/// The reason naming the offending field is returned on the first violation.
+pub fn inspect_container_create(
+    rules: &ContainerCreate,
+    api: CreateApi,
+    body: &Value,
+) -> Result<(), String> {
+    let body = match body.as_object() {
+        Some(body) => body,
+        None => return Err(String::from("body is not a JSON object")),
+    };
+
+    match api {
+        CreateApi::Compat => {
+            for host_config in fields(body, "HostConfig") {
+                if let Some(host_config) = host_config.as_object() {
+                    inspect_compat(rules, host_config)?;
+                }
+            }
+            Ok(())
+        }
+        CreateApi::Libpod => inspect_libpod(rules, body),
+    }
+}
+
+/// Get the images and root filesystems a container create body runs
+pub fn container_create_sources(api: CreateApi, body: &Value) -> (Vec<&str>, Vec<&str>) {
+    let body = match body.as_object() {
+        Some(body) => body,
+        None => return (Vec::new(), Vec::new()),
+    };
+
+    let images = fields(body, "Image").filter_map(Value::as_str).collect();
+    let rootfs = match api {
+        CreateApi::Compat => Vec::new(),
+        CreateApi::Libpod => fields(body, "rootfs")
+            .filter_map(Value::as_str)
+            .filter(|rootfs| !rootfs.is_empty())
+            .collect(),
+    };
+
+    (images, rootfs)
+}
+
+fn inspect_compat(rules: &ContainerCreate, host_config: &Map<String, Value>) -> Result<(), String> {
+    if !rules.allow_privileged && any_true(host_config, "Privileged") {
+        return Err(String::from("HostConfig.Privileged is not allowed"));
+    }
+
+    if !rules.allow_capabilities && any_non_empty(host_config, "CapAdd") {
+        return Err(String::from("HostConfig.CapAdd is not allowed"));
+    }
+
+    if !rules.allow_host_network && any_equals(host_config, "NetworkMode", "host") {
+        return Err(String::from("HostConfig.NetworkMode=host is not allowed"));
+    }
+
+    if !rules.allow_host_pid && any_equals(host_config, "PidMode", "host") {
+        return Err(String::from("HostConfig.PidMode=host is not allowed"));
+    }
+
+    if !rules.allow_host_ipc && any_equals(host_config, "IpcMode", "host") {
+        return Err(String::from("HostConfig.IpcMode=host is not allowed"));
+    }
+
+    if !rules.allow_devices {
+        if any_non_empty(host_config, "Devices") {
+            return Err(String::from("HostConfig.Devices is not allowed"));
+        }
+        if any_non_empty(host_config, "DeviceCgroupRules") {
+            return Err(String::from("HostConfig.DeviceCgroupRules is not allowed"));
+        }
+    }
+
+    for bind in fields(host_config, "Binds")
+        .filter_map(Value::as_array)
+        .flatten()
+    {
+        let source = bind
+            .as_str()
+            .and_then(|bind| bind.split(':').next())
+            .unwrap_or_default();
+
+        if is_host_path(source) && !is_bind_allowed(rules, source) {
+            return Err(format!("HostConfig.Binds source {} is not allowed", source));
+        }
+    }
+
+    for mount in fields(host_config, "Mounts")
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(Value::as_object)
+    {
+        let is_volume = any_equals(mount, "Type", "volume");
+
+        // A local volume can bind any host path through its driver options
+        if is_volume
+            && fields(mount, "VolumeOptions")
+                .filter_map(Value::as_object)
+                .any(|options| any_non_null(options, "DriverConfig"))
+        {
+            return Err(String::from(
+                "HostConfig.Mounts.VolumeOptions.DriverConfig is not allowed",
+            ));
+        }
+
+        if let Some(source) = forbidden_mount_source(rules, mount, "Type", "Source") {
+            return Err(format!(
+                "HostConfig.Mounts source {} is not allowed",
+                source
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn inspect_libpod(rules: &ContainerCreate, spec: &Map<String, Value>) -> Result<(), String> {
+    if !rules.allow_privileged && any_true(spec, "privileged") {
+        return Err(String::from("privileged is not allowed"));
+    }
+
+    if !rules.allow_capabilities && any_non_empty(spec, "cap_add") {
+        return Err(String::from("cap_add is not allowed"));
+    }
+
+    let namespaces = [
+        ("netns", rules.allow_host_network),
+        ("pidns", rules.allow_host_pid),
+        ("ipcns", rules.allow_host_ipc),
+    ];
+    for (namespace, allowed) in namespaces {
+        let is_host = fields(spec, namespace)
+            .filter_map(Value::as_object)
+            .any(|ns| any_equals(ns, "nsmode", "host"));
+
+        if !allowed && is_host {
+            return Err(format!("{}.nsmode=host is not allowed", namespace));
+        }
+    }
+
+    if !rules.allow_devices {
+        if any_non_empty(spec, "devices") {
+            return Err(String::from("devices is not allowed"));
+        }
+        if any_non_empty(spec, "device_cgroup_rule") {
+            return Err(String::from("device_cgroup_rule is not allowed"));
+        }
+    }
+
+    for mount in fields(spec, "mounts")
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(Value::as_object)
+    {
+        if let Some(source) = forbidden_mount_source(rules, mount, "type", "source") {
+            return Err(format!("mounts source {} is not allowed", source));
+        }
+    }
+
+    // The host directory used as the root filesystem of the container
+    for rootfs in fields(spec, "rootfs")
+        .filter_map(Value::as_str)
+        .filter(|rootfs| !rootfs.is_empty())
+    {
+        if !is_bind_allowed(rules, rootfs) {
+            return Err(format!("rootfs {} is not allowed", rootfs));
+        }
+    }
+
+    for volume in fields(spec, "overlay_volumes")
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(Value::as_object)
+    {
+        for source in fields(volume, "source").filter_map(Value::as_str) {
+            if !is_bind_allowed(rules, source) {
+                return Err(format!("overlay_volumes source {} is not allowed", source));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the source of a mount binding a host path outside of the allowed bind paths
+fn forbidden_mount_source<'a>(
+    rules: &ContainerCreate,
+    mount: &'a Map<String, Value>,
+    type_field: &str,
+    source_field: &str,
+) -> Option<&'a str> {
+    let is_bind = any_equals(mount, type_field, "bind");
+
+    fields(mount, source_field)
+        .filter_map(Value::as_str)
+        .find(|source| (is_bind || is_host_path(source)) && !is_bind_allowed(rules, source))
+}
+
+/// Fold a JSON key the way Go compares it to a struct field name
+fn fold_key(key: &str) -> String {
+    key.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c == 'ſ' { 's' } else { c })
+        .collect()
+}
+
+/// Get every value of an object whose key matches the field name
+fn fields<'a>(object: &'a Map<String, Value>, name: &str) -> impl Iterator<Item = &'a Value> {
+    let name = fold_key(name);
+
+    object
+        .iter()
+        .filter(move |(key, _)| fold_key(key) == name)
+        .map(|(_, value)| value)
+}
+
+fn any_true(object: &Map<String, Value>, name: &str) -> bool {
+    fields(object, name).any(|value| value.as_bool() == Some(true))
+}
+
+fn any_non_null(object: &Map<String, Value>, name: &str) -> bool {
+    fields(object, name).any(|value| !value.is_null())
+}
+
+fn any_non_empty(object: &Map<String, Value>, name: &str) -> bool {
+    fields(object, name).any(|value| match value {
+        Value::Array(values) => !values.is_empty(),
+        Value::Null => false,
+        _ => true,
+    })
+}
+
+fn any_equals(object: &Map<String, Value>, name: &str, expected: &str) -> bool {
+    fields(object, name).any(|value| {
+        value
+            .as_str()
+            .is_some_and(|value| value.eq_ignore_ascii_case(expected))
+    })
+}
+
+/// Check if a bind source refers to the host filesystem rather than a named volume
+fn is_host_path(source: &str) -> bool {
+    source.starts_with('/') || source.starts_with('.') || source.starts_with('~')
+}
+
+/// Check if a host path is inside one of the allowed bind paths
+///
+/// Paths are compared lexically, symbolic links on the host are not resolved.
+fn is_bind_allowed(rules: &ContainerCreate, source: &str) -> bool {
+    let source = match normalize_host_path(source) {
+        Some(source) => source,
+        None => return false,
+    };
+
+    rules.allowed_bind_paths.iter().any(|allowed| {
+        normalize_host_path(allowed).is_some_and(|allowed| {
+            allowed == "/"
+                || source == allowed
+                || source
+                    .strip_prefix(&allowed)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    })
+}
+
+/// Resolve `.` and `..` segments of an absolute path, `None` for relative paths
+fn normalize_host_path(path: &str) -> Option<String> {
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    Some(format!("/{}", segments.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn rules(allowed_bind_paths: &[&str]) -> ContainerCreate {
+        ContainerCreate {
+            allowed_bind_paths: allowed_bind_paths
+                .iter()
+                .map(|path| path.to_string())
+                .collect(),
+            ..ContainerCreate::default()
+        }
+    }
+
+    fn compat(rules: &ContainerCreate, body: Value) -> Result<(), String> {
+        inspect_container_create(rules, CreateApi::Compat, &body)
+    }
+
+    fn libpod(rules: &ContainerCreate, body: Value) -> Result<(), String> {
+        inspect_container_create(rules, CreateApi::Libpod, &body)
+    }
+
+    #[test]
+    fn create_api() {
+        assert!(container_create_api("/containers/create") == Some(CreateApi::Compat));
+        assert!(container_create_api("/libpod/containers/create") == Some(CreateApi::Libpod));
+        assert!(container_create_api("/containers/x/start").is_none());
+        assert!(is_volume_create("/libpod/volumes/create"));
+        assert!(!is_volume_create("/volumes/prune"));
+    }
+
+    #[test]
+    fn folds_the_case_of_keys() {
+        let rules = rules(&[]);
+
+        assert_eq!(
+            compat(&rules, json!({"hostconfig": {"PRIVILEGED": true}})),
+            Err(String::from("HostConfig.Privileged is not allowed"))
+        );
+        assert_eq!(
+            compat(
+                &rules,
+                json!({"HostConfig": {"ſecurityOpt": [], "privileged": true}})
+            ),
+            Err(String::from("HostConfig.Privileged is not allowed"))
+        );
+        assert_eq!(
+            libpod(&rules, json!({"Cap_Add": ["SYS_ADMIN"]})),
+            Err(String::from("cap_add is not allowed"))
+        );
+        // Every key matching a field is checked, not only the last one
+        assert_eq!(
+            compat(
+                &rules,
+                json!({"HostConfig": {"NetworkMode": "host", "networkmode": "bridge"}})
+            ),
+            Err(String::from("HostConfig.NetworkMode=host is not allowed"))
+        );
+        assert!(compat(&rules, json!({"HostConfig": {"Privileged": false}})).is_ok());
+    }
+
+    #[test]
+    fn checks_binds() {
+        let rules = rules(&["/srv/data"]);
+
+        assert!(compat(
+            &rules,
+            json!({"HostConfig": {"Binds": ["/srv/data/app:/app:ro"]}})
+        )
+        .is_ok());
+        assert!(compat(&rules, json!({"HostConfig": {"Binds": ["cache:/cache"]}})).is_ok());
+        assert_eq!(
+            compat(&rules, json!({"HostConfig": {"Binds": ["/etc:/host-etc"]}})),
+            Err(String::from("HostConfig.Binds source /etc is not allowed"))
+        );
+        assert_eq!(
+            compat(
+                &rules,
+                json!({"HostConfig": {"Binds": ["/srv/database:/db"]}})
+            ),
+            Err(String::from(
+                "HostConfig.Binds source /srv/database is not allowed"
+            ))
+        );
+        // Relative paths are resolved by the client, they are never allowed
+        assert!(compat(&rules, json!({"HostConfig": {"Binds": ["./data:/data"]}})).is_err());
+    }
+
+    #[test]
+    fn resolves_parent_segments_of_bind_paths() {
+        assert!(is_bind_allowed(&rules(&["/"]), "/../etc"));
+
+        let rules = rules(&["/srv/data"]);
+
+        assert_eq!(
+            compat(
+                &rules,
+                json!({"HostConfig": {"Binds": ["/srv/data/../../etc:/etc"]}})
+            ),
+            Err(String::from(
+                "HostConfig.Binds source /srv/data/../../etc is not allowed"
+            ))
+        );
+        assert!(compat(
+            &rules,
+            json!({"HostConfig": {"Binds": ["/srv/x/../data/./app:/app"]}})
+        )
+        .is_ok());
+        assert!(is_bind_allowed(&rules, "/srv/data/"));
+        assert!(!is_bind_allowed(&rules, "/srv/data/../.."));
+    }
+
+    #[test]
+    fn checks_mounts() {
+        let rules = rules(&["/srv/data"]);
+
+        assert_eq!(
+            compat(
+                &rules,
+                json!({"HostConfig": {"Mounts": [{"Type": "bind", "Source": "/var/run"}]}})
+            ),
+            Err(String::from(
+                "HostConfig.Mounts source /var/run is not allowed"
+            ))
+        );
+        assert_eq!(
+            compat(
+                &rules,
+                json!({"HostConfig": {"Mounts": [{"type": "BIND", "source": "relative"}]}})
+            ),
+            Err(String::from(
+                "HostConfig.Mounts source relative is not allowed"
+            ))
+        );
+        assert!(compat(
+            &rules,
+            json!({"HostConfig": {"Mounts": [{"Type": "volume", "Source": "cache"}]}})
+        )
+        .is_ok());
+        assert_eq!(
+            libpod(&rules, json!({"mounts": [{"type": "bind", "source": "/"}]})),
+            Err(String::from("mounts source / is not allowed"))
+        );
+        assert!(libpod(
+            &rules,
+            json!({"mounts": [{"type": "bind", "source": "/srv/data/x"}]})
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_volume_driver_config() {
+        let rules = rules(&[]);
+        let mount = json!({
+            "Type": "volume",
+            "Source": "data",
+            "VolumeOptions": {
+                "DriverConfig": {"Name": "local", "Options": {"type": "none", "o": "bind", "device": "/"}}
+            }
+        });
+
+        assert_eq!(
+            compat(&rules, json!({"HostConfig": {"Mounts": [mount]}})),
+            Err(String::from(
+                "HostConfig.Mounts.VolumeOptions.DriverConfig is not allowed"
+            ))
+        );
+        assert!(compat(
+            &rules,
+            json!({"HostConfig": {"Mounts": [{"Type": "volume", "Source": "data", "VolumeOptions": {"DriverConfig": null}}]}})
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn checks_overlay_volumes_and_rootfs() {
+        let rules = rules(&["/srv/data"]);
+
+        assert_eq!(
+            libpod(
+                &rules,
+                json!({"overlay_volumes": [{"source": "/etc", "destination": "/etc"}]})
+            ),
+            Err(String::from("overlay_volumes source /etc is not allowed"))
+        );
+        assert!(libpod(
+            &rules,
+            json!({"overlay_volumes": [{"source": "/srv/data/etc"}]})
+        )
+        .is_ok());
+        assert_eq!(
+            libpod(&rules, json!({"rootfs": "/"})),
+            Err(String::from("rootfs / is not allowed"))
+        );
+        assert!(libpod(&rules, json!({"rootfs": "/srv/data/root"})).is_ok());
+        assert!(libpod(&rules, json!({"rootfs": ""})).is_ok());
+    }
+
+    #[test]
+    fn checks_volume_devices() {
+        let rules = rules(&["/srv/data"]);
+
+        assert_eq!(
+            inspect_volume_create(
+                &rules,
+                &json!({"Name": "x", "DriverOpts": {"type": "none", "o": "bind", "device": "/etc"}})
+            ),
+            Err(String::from("volume device /etc is not allowed"))
+        );
+        assert_eq!(
+            inspect_volume_create(&rules, &json!({"options": {"Device": "/dev/sda1"}})),
+            Err(String::from("volume device /dev/sda1 is not allowed"))
+        );
+        assert!(inspect_volume_create(
+            &rules,
+            &json!({"DriverOpts": {"type": "tmpfs", "device": "tmpfs"}})
+        )
+        .is_ok());
+        assert!(
+            inspect_volume_create(&rules, &json!({"Options": {"device": "/srv/data/v"}})).is_ok()
+        );
+        assert!(inspect_volume_create(&rules, &json!([])).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+
+use crate::config::{self, Config};
+use crate::path;
+
+lazy_static! {
+    static ref ID_SEGMENT: Regex = Regex::new(r"^[0-9a-f]{12,64}$").unwrap();
+}
+
+/// The config used while learning, allowing every request
+const ALLOW_ALL: &str = r#"
+[[filters.rules]]
+name = "learn"
+action = "allow"
+regex = "^/"
+
+[container_create]
+allow_privileged = true
+allow_capabilities = true
+allow_host_network = true
+allow_host_pid = true
+allow_host_ipc = true
+allow_devices = true
+allowed_bind_paths = ["/"]
+"#;
+
+/// Get the config allowing every request, used while learning
+pub fn allow_all_config() -> Result<Config, toml::de::Error> {
+    toml::from_str(ALLOW_ALL)
+}
+
+/// A route template of the built-in groups, compiled
+struct Template {
+    method: &'static str,
+    route: &'static str,
+    regex: Regex,
+    placeholders: usize,
+}
+
+/// Records the requests seen while learning, as route templates by method
+pub struct Learner {
+    output: String,
+    templates: Vec<Template>,
+    routes: Mutex<BTreeMap<String, BTreeSet<String>>>,
+}
+
+impl Learner {
+    pub fn new(output: String) -> Self {
+        let mut routes = config::group_routes();
+        routes.sort_unstable();
+        routes.dedup();
+
+        let templates = routes
+            .into_iter()
+            .filter_map(|(method, route)| {
+                let regex = path::route_to_regex(route).ok()?;
+
+                Some(Template {
+                    method,
+                    route,
+                    regex: Regex::new(&regex).ok()?,
+                    placeholders: route.matches('{').count(),
+                })
+            })
+            .collect();
+
+        Learner {
+            output,
+            templates,
+            routes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Record a request by its method and canonical path
+    pub fn record(&self, method: &str, canonical: &str) {
+        let route = self.collapse(method, canonical);
+
+        if let Ok(mut routes) = self.routes.lock() {
+            if routes
+                .entry(method.to_lowercase())
+                .or_default()
+                .insert(route.clone())
+            {
+                log::info!("Learned {} {}", method, route);
+            }
+        }
+    }
+
+    /// Replace the IDs and names of a path with placeholders
+    ///
+    /// The most specific route template of the built-in groups matching the
+    /// request is used, otherwise the segments looking like IDs are replaced.
+    fn collapse(&self, method: &str, canonical: &str) -> String {
+        if let Some(template) = self
+            .templates
+            .iter()
+            .filter(|template| template.method.eq_ignore_ascii_case(method))
+            .filter(|template| template.regex.is_match(canonical))
+            .min_by_key(|template| template.placeholders)
+        {
+            return template.route.to_string();
+        }
+
+        canonical
+            .split('/')
+            .map(|segment| match ID_SEGMENT.is_match(segment) {
+                true => "{id}",
+                false => segment,
+            })
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+
+    /// Build the TOML policy allowing the recorded requests
+    fn policy(&self) -> String {
+        let routes = match self.routes.lock() {
+            Ok(routes) => routes.clone(),
+            Err(_) => BTreeMap::new(),
+        };
+
+        let mut policy = String::from(
+            "# Policy learned by podman-socket-proxy\n\
+             #\n\
+             # Container creation restrictions and the images allowlist are not learned,\n\
+             # see the [container_create] and [images] sections of the documentation.\n",
+        );
+
+        for (method, routes) in routes {
+            for route in routes {
+                let quoted = |value: &str| toml::Value::String(value.to_string()).to_string();
+
+                // Segments a route template cannot hold are matched literally
+                let matcher = match path::route_to_regex(&route) {
+                    Ok(_) => format!("route = {}", quoted(&route)),
+                    Err(_) => format!(
+                        "canonical = {}",
+                        quoted(&format!("^{}$", regex::escape(&route)))
+                    ),
+                };
+
+                policy.push_str(&format!(
+                    "\n[[filters.{}.rules]]\nname = {}\naction = \"allow\"\n{}\n",
+                    method,
+                    quoted(&route),
+                    matcher
+                ));
+            }
+        }
+
+        policy
+    }
+
+    /// Write the learned policy to the output file
+    pub fn write(&self) -> io::Result<()> {
+        fs::write(&self.output, self.policy())?;
+        log::info!("Learned policy written to {}", self.output);
+
+        Ok(())
+    }
+}
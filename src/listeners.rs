@@ -0,0 +1,78 @@
+use anyhow::bail;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use crate::cli::UnixProxyArgs;
+use crate::config::{Listener, Socket, Tls};
+use crate::proxy::{self, ProxyListener};
+
+impl Listener {
+    /// Get the name of the listener in the logs, its address by default
+    pub fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.unix.as_deref())
+            .or(self.inet.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Check that the listener sets a single valid address
+    ///
+    /// # Errors
+    ///
+    /// The reason is returned if it does not.
+    pub fn check(&self) -> Result<(), String> {
+        match (&self.unix, &self.inet) {
+            (Some(_), None) => Ok(()),
+            (None, Some(address)) => match address.parse::<SocketAddr>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(format!(
+                    "invalid inet address {}, expected an IP address and a port",
+                    address
+                )),
+            },
+            _ => Err(String::from("set either unix or inet")),
+        }
+    }
+}
+
+/// Open the socket of a listener of the config
+///
+/// The permissions of the listener, else those of `[socket]`, apply to Unix
+/// sockets, and `[tls]` to TCP sockets.
+pub async fn open(
+    listener: &Listener,
+    socket: &Socket,
+    tls: &Tls,
+) -> anyhow::Result<(ProxyListener, Option<PathBuf>)> {
+    if let Err(reason) = listener.check() {
+        bail!(reason);
+    }
+
+    match (&listener.unix, &listener.inet) {
+        (Some(socket_path), None) => {
+            let args = UnixProxyArgs {
+                socket_path: socket_path.clone(),
+                replace: listener.replace,
+                mode: listener.socket.mode.clone(),
+                owner: listener.socket.owner.clone(),
+                group: listener.socket.group.clone(),
+            };
+            let unix_socket = proxy::unix::open_unix_socket(&args, socket).await?;
+
+            log::info!(
+                "Listening on: {:?} ({})",
+                socket_path,
+                listener.display_name()
+            );
+            Ok((unix_socket.into(), Some(PathBuf::from(socket_path))))
+        }
+        (None, Some(address)) => {
+            let inet_socket = proxy::tcp::open_inet_address(address, tls).await?;
+
+            log::info!("Listening on: {} ({})", address, listener.display_name());
+            Ok((inet_socket, None))
+        }
+        _ => unreachable!("the listener is checked above"),
+    }
+}
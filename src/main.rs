@@ -1,32 +1,85 @@
+mod admin;
+mod audit;
+mod check;
 mod cli;
+mod clients;
 mod config;
 mod errors;
+mod explain;
 mod filter;
+mod images;
+mod inspect;
+mod learn;
+mod listeners;
+mod metrics;
+mod path;
+mod policy;
 mod proxy;
+mod query;
+mod reload;
 mod responses;
 
-use crate::responses::request_response;
-use anyhow::Context;
+use anyhow::{bail, Context};
 use errors::ConnectPodmanError;
-use log;
 use proxy::client::handle_client;
+use sd_notify::NotifyState;
 use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs;
-use tokio::io::{AsyncReadExt, BufReader};
 use tokio::net::UnixStream;
-use tokio::sync::{mpsc, Semaphore};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch, Semaphore};
 use env_logger::Env;
 
 const MAX_CONCURRENT_CONNECTIONS: usize = 10000;
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+const CONNECT_RETRY_DELAY: Duration = Duration::from_millis(100);
+const CONNECT_MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Time given to the connections closed on shutdown to send their last bytes
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
 struct PodmanSocketConnector {
     podman_path: String,
+    /// Connection attempts after a failed one, for the clients
+    retries: u32,
 }
 
 impl PodmanSocketConnector {
-    pub fn new(podman_path: String) -> Self {
-        PodmanSocketConnector { podman_path }
+    pub fn new(podman_path: String, retries: u32) -> Self {
+        PodmanSocketConnector {
+            podman_path,
+            retries,
+        }
+    }
+
+    /// Connect to the Podman socket for a client, retrying with an exponential backoff
+    pub async fn connect_retrying(&self) -> Result<UnixStream, ConnectPodmanError> {
+        let mut delay = CONNECT_RETRY_DELAY;
+        let mut attempt = 0;
+
+        loop {
+            match self.connect().await {
+                Ok(podman_sock) => return Ok(podman_sock),
+                Err(e) => {
+                    metrics::upstream_connect_failed();
+
+                    if attempt >= self.retries {
+                        return Err(e);
+                    }
+                    log::warn!(
+                        "Failed to connect to the Podman API, retrying in {:?}: {}",
+                        delay,
+                        e
+                    );
+                }
+            }
+
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(CONNECT_MAX_RETRY_DELAY);
+            attempt += 1;
+        }
     }
 
     pub async fn connect(&self) -> Result<UnixStream, ConnectPodmanError> {
@@ -44,43 +97,157 @@ impl PodmanSocketConnector {
     }
 }
 
-//
-// Main entrypoint
-//
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+/// Open the socket the proxy listens on
+///
+/// The path of the socket file created is returned with the listener.
+async fn open_listener(
+    listener: cli::Listener,
+    socket: &config::Socket,
+    tls: &config::Tls,
+) -> anyhow::Result<(proxy::ProxyListener, Option<PathBuf>)> {
+    match listener {
+        cli::Listener::Inet(args) => {
+            let inet_socket = proxy::tcp::open_inet_socket(&args, tls).await?;
 
-    let args = cli::get_args();
+            log::info!("Listening on: {}:{}", args.ip, args.port);
+            Ok((inet_socket, None))
+        }
+        cli::Listener::Unix(args) => {
+            let unix_socket = proxy::unix::open_unix_socket(&args, socket).await?;
 
-    let config = config::get_config(&args.config_path)
-        .with_context(|| format!("Failed to parse config file at {}", &args.config_path))?;
+            log::info!("Listening on: {:?}", args.socket_path);
+            Ok((
+                proxy::ProxyListener::Unix(unix_socket),
+                Some(PathBuf::from(args.socket_path)),
+            ))
+        }
+        // The socket file is managed by systemd
+        cli::Listener::Systemd(args) => {
+            let listener = proxy::systemd::inherited_listener(args.name.as_deref())?;
+            Ok((proxy::tls::wrap_listener(listener, tls)?, None))
+        }
+    }
+}
 
-    let listener = match args.proxy {
-        cli::Proxy::Inet(args) => {
-            let inet_socket = proxy::tcp::open_inet_socket(&args).await?;
+/// The listeners of the proxy with their name and own filters, `None` for the
+/// policy of the config file
+type Listeners = Vec<(
+    proxy::ProxyListener,
+    Option<String>,
+    Option<filter::FiltersHandler>,
+)>;
 
-            log::info!("Listening on: {}:{}", args.ip, args.port);
-            proxy::ProxyListener::Inet(inet_socket)
+/// Open the listener of the command line, or every `[[listeners]]` entry, and
+/// the admin listener
+///
+/// The path of each socket file is added to `socket_paths` once created, for
+/// the caller to remove them if a later listener fails to open.
+async fn open_listeners(
+    cli_listener: Option<cli::Listener>,
+    config: &config::Config,
+    config_path: &str,
+    admin_listen: Option<&str>,
+    watch_config: bool,
+    socket_paths: &mut Vec<PathBuf>,
+) -> anyhow::Result<(Listeners, Option<proxy::ProxyListener>)> {
+    let mut listeners = Vec::new();
+    match cli_listener {
+        Some(cli_listener) => {
+            let (listener, socket_path) =
+                open_listener(cli_listener, &config.socket, &config.tls).await?;
+            socket_paths.extend(socket_path);
+            listeners.push((listener, None, None));
         }
-        cli::Proxy::Unix(args) => {
-            let unix_socket = proxy::unix::open_unix_socket(&args).await?;
+        None => {
+            if config.listeners.is_empty() {
+                bail!("No [[listeners]] in the config file {}", config_path);
+            }
 
-            log::info!("Listening on: {:?}", args.socket_path);
-            proxy::ProxyListener::Unix(unix_socket)
+            for (index, entry) in config.listeners.iter().enumerate() {
+                let (listener, socket_path) = listeners::open(entry, &config.socket, &config.tls)
+                    .await
+                    .with_context(|| format!("Failed to open listeners[{}]", index))?;
+                socket_paths.extend(socket_path);
+
+                let filters_handler = reload::load_filters(
+                    entry.config.as_deref(),
+                    entry.profile.as_deref(),
+                    watch_config,
+                )
+                .with_context(|| format!("Failed to load the policy of listeners[{}]", index))?;
+                listeners.push((listener, entry.name.clone(), filters_handler));
+            }
+        }
+    }
+
+    let admin_listener = match admin_listen {
+        Some(address) => {
+            let (admin_listener, admin_socket_path) = admin::open_listener(address)
+                .await
+                .with_context(|| format!("Failed to open the admin listener {}", address))?;
+            socket_paths.extend(admin_socket_path);
+
+            log::info!("Serving metrics and health checks on: {}", address);
+            Some(admin_listener)
         }
+        None => None,
     };
 
-    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_CONNECTIONS));
-    let podman_connector = PodmanSocketConnector::new(args.podman_path.clone());
-    let filters_handler = filter::FiltersHandler::new(config.filters);
+    Ok((listeners, admin_listener))
+}
+
+/// Wait for SIGINT or SIGTERM
+async fn shutdown_signal() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+/// Remove the socket files created by the proxy
+async fn remove_socket_files(socket_paths: &[PathBuf]) {
+    for socket_path in socket_paths {
+        if let Err(e) = fs::remove_file(socket_path).await {
+            log::warn!("Failed to remove the socket file {:?}: {}", socket_path, e);
+        }
+    }
+}
+
+/// Shared by the listeners of the proxy
+#[derive(Clone)]
+struct ProxyContext {
+    semaphore: Arc<Semaphore>,
+    podman_connector: Arc<PodmanSocketConnector>,
+    audit: audit::AuditLog,
+    clients: Arc<clients::ClientPolicies>,
+    shutdown: watch::Receiver<bool>,
+    /// Set once the shutdown timeout expires, closing the connections left
+    closing: watch::Receiver<bool>,
+}
+
+/// Accept the clients of a listener until the proxy shuts down
+///
+/// `name` is the name of the `[[listeners]]` entry, selecting the client entries
+/// scoped to it.
+async fn serve(
+    listener: proxy::ProxyListener,
+    name: Option<String>,
+    filters_handler: filter::FiltersHandler,
+    context: ProxyContext,
+) {
+    let mut shutdown = context.shutdown.clone();
 
     loop {
-        match listener.accept().await {
-            Ok(stream) => {
-                log::debug!("Accepted a client connection");
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = proxy::shutting_down(&mut shutdown) => break,
+        };
 
-                let permit = match semaphore.clone().acquire_owned().await {
+        match accepted {
+            Ok(accepted) => {
+                let permit = match context.semaphore.clone().acquire_owned().await {
                     Ok(permit) => permit,
                     Err(_) => {
                         log::error!("Too many clients, client connection closed");
@@ -88,84 +255,320 @@ async fn main() -> anyhow::Result<()> {
                     }
                 };
 
-                let filters_handler = filters_handler.clone();
+                let mut filters_handler = filters_handler.clone();
+                let client_policies = context.clients.clone();
+                let listener_name = name.clone();
+                let podman_connector = context.podman_connector.clone();
+                let audit = context.audit.clone();
+                let handler_shutdown = context.shutdown.clone();
+                let receiver_shutdown = context.shutdown.clone();
+                let mut closing = context.closing.clone();
+                let connection = metrics::connection_started();
 
-                let podman_sock = match podman_connector.connect().await {
-                    Ok(sock) => sock,
-                    Err(e) => {
-                        log::error!("Failed to establish connection with the Podman API : {}", e);
-                        break;
+                // Connecting to Podman may be retried, without blocking other clients
+                tokio::spawn(async move {
+                    let stream = match accepted.establish().await {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            log::warn!("TLS handshake failed: {}", e);
+                            return;
+                        }
+                    };
+                    let client = stream.identity();
+
+                    match client_policies.select(&client, listener_name.as_deref()) {
+                        Some((entry, client_filters)) => {
+                            log::info!(
+                                "Client {} connected, policy of {}",
+                                client,
+                                entry.display_name()
+                            );
+                            filters_handler = client_filters.clone();
+                        }
+                        None => log::debug!("Client {} connected", client),
                     }
-                };
-                let (podman_read, podman_write) = podman_sock.into_split();
 
-                let (stream_read, mut stream_write) = stream.split();
+                    let podman_sock = match podman_connector.connect_retrying().await {
+                        Ok(sock) => sock,
+                        Err(e) => {
+                            log::error!(
+                                "Failed to establish connection with the Podman API for {}: {}",
+                                client,
+                                e
+                            );
+                            let response = responses::service_unavailable(&e.to_string());
+                            if let Err(e) = proxy::client::reject_client(stream, &response).await {
+                                log::debug!("Error writing to a client: {}", e);
+                            }
+                            return;
+                        }
+                    };
+                    let (podman_read, podman_write) = podman_sock.into_split();
 
-                let (tx, mut rx) = mpsc::channel(1024);
-                let handler_tx = tx.clone();
-                let receiver_tx = tx.clone();
+                    let (stream_read, mut stream_write) = stream.split();
 
-                tokio::spawn(async move {
-                    if let Err(e) =
-                        handle_client(stream_read, podman_write, handler_tx, filters_handler).await
-                    {
-                        log::error!("Error occured while handling a client: {}", e);
-                    }
+                    let (tx, mut rx) = mpsc::channel(1024);
+                    let handler_tx = tx.clone();
+                    let receiver_tx = tx.clone();
+                    let (forwarded_tx, forwarded_rx) = mpsc::unbounded_channel();
+                    let handler_audit = audit.clone();
+                    let receiver_audit = audit.clone();
 
-                    drop(permit);
-                });
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_client(
+                            stream_read,
+                            podman_write,
+                            handler_tx,
+                            filters_handler,
+                            client,
+                            forwarded_tx,
+                            handler_audit,
+                            handler_shutdown,
+                        )
+                        .await
+                        {
+                            log::error!("Error occured while handling a client: {}", e);
+                        }
+                    });
 
-                tokio::spawn(async move {
-                    while let Some(message) = rx.recv().await {
-                        match stream_write.write(&message.buffer).await {
-                            Ok(_) => {
-                                if message.close {
+                    // The connection is counted until the client stream is shut down
+                    tokio::spawn(async move {
+                        loop {
+                            let message = tokio::select! {
+                                message = rx.recv() => message,
+                                _ = proxy::shutting_down(&mut closing) => break,
+                            };
+                            let Some(message) = message else {
+                                break;
+                            };
+
+                            match stream_write.write(&message.buffer).await {
+                                Ok(_) => {
+                                    if message.close {
+                                        break;
+                                    }
+                                }
+                                Err(e) => {
+                                    log::error!("Error writing to a client: {}", e);
                                     break;
                                 }
                             }
-                            Err(e) => {
-                                log::error!("Error writing to a client: {}", e);
-                                break;
-                            }
                         }
-                    }
-                });
-
-                tokio::spawn(async move {
-                    let mut podman_buffer_reader = BufReader::new(podman_read);
-
-                    let mut response_buffer: Vec<u8> = Vec::with_capacity(1024 * 1024);
-                    loop {
-                        let size = match podman_buffer_reader.read_buf(&mut response_buffer).await {
-                            Ok(size) => size,
-                            Err(e) => {
-                                log::error!("Error reading from the Podman socket: {}", e);
-                                break;
-                            }
-                        };
 
-                        if size == 0 {
-                            break;
-                        }
+                        let _ = stream_write.shutdown().await;
 
-                        if let Err(e) = receiver_tx
-                            .send(request_response(response_buffer.clone()))
-                            .await
-                        {
-                            log::error!("Error sending response to a client: {}", e);
-                            break;
-                        }
+                        drop(connection);
+                        drop(permit);
+                    });
 
-                        response_buffer.clear();
-                    }
+                    tokio::spawn(proxy::upstream::forward_responses(
+                        podman_read,
+                        receiver_tx,
+                        forwarded_rx,
+                        receiver_audit,
+                        receiver_shutdown,
+                    ));
                 });
             }
             Err(err) => {
+                // e.g. too many open files, clients may be accepted once others are closed
                 log::error!("Error accepting client: {}", err);
-                break;
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
             }
         }
     }
+}
+
+//
+// Main entrypoint
+//
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let args = cli::get_args();
+
+    if let cli::Proxy::Check(check_args) = &args.proxy {
+        let valid = check::check_config(
+            &args.config_path,
+            args.profile.as_deref(),
+            check_args.deny_warnings,
+        );
+        std::process::exit(if valid { 0 } else { 1 });
+    }
+
+    let config = match &args.proxy {
+        cli::Proxy::Learn(_) => {
+            log::warn!("Learning mode, every request is allowed");
+            learn::allow_all_config()?
+        }
+        _ => config::get_config(&args.config_path, args.profile.as_deref())
+            .with_context(|| format!("Failed to parse config file at {}", &args.config_path))?,
+    };
+    if let Some(profile) = &config.profile {
+        log::info!("Using profile: {}", profile);
+    }
+
+    let policy = config::compile_policy(&config)
+        .with_context(|| format!("Failed to compile filters of {}", &args.config_path))?;
+
+    if let cli::Proxy::Explain(explain_args) = &args.proxy {
+        let (_, policy_rx) = watch::channel(policy);
+        let allowed = explain::explain(&filter::FiltersHandler::new(policy_rx), explain_args)?;
+        std::process::exit(if allowed { 0 } else { 1 });
+    }
+
+    let mut learner = None;
+    let cli_listener = match args.proxy {
+        cli::Proxy::Inet(args) => Some(cli::Listener::Inet(args)),
+        cli::Proxy::Unix(args) => Some(cli::Listener::Unix(args)),
+        cli::Proxy::Systemd(args) => Some(cli::Listener::Systemd(args)),
+        cli::Proxy::Learn(learn_args) => {
+            learner = Some(Arc::new(learn::Learner::new(learn_args.output)));
+            Some(learn_args.listener)
+        }
+        cli::Proxy::Serve => None,
+        cli::Proxy::Check(_) | cli::Proxy::Explain(_) => {
+            unreachable!("the command runs before starting the proxy")
+        }
+    };
+
+    let client_policies =
+        clients::ClientPolicies::load(&config.clients, &config.listeners, args.watch_config)?;
+
+    let audit = match &args.audit_log {
+        Some(destination) => audit::AuditLog::open(destination)
+            .await
+            .with_context(|| format!("Failed to open the audit log {}", destination))?,
+        None => audit::AuditLog::disabled(),
+    };
+
+    // The sockets opened before one fails are removed before exiting
+    let mut socket_paths: Vec<PathBuf> = Vec::new();
+    let opened = open_listeners(
+        cli_listener,
+        &config,
+        &args.config_path,
+        args.admin_listen.as_deref(),
+        args.watch_config,
+        &mut socket_paths,
+    )
+    .await;
+    let (listeners, admin_listener) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            remove_socket_files(&socket_paths).await;
+            return Err(e);
+        }
+    };
+
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_CONNECTIONS));
+    let podman_connector = Arc::new(PodmanSocketConnector::new(
+        args.podman_path.clone(),
+        args.upstream_retries,
+    ));
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let (closing_tx, closing_rx) = watch::channel(false);
+
+    metrics::init(MAX_CONCURRENT_CONNECTIONS);
+    if let Some(admin_listener) = admin_listener {
+        tokio::spawn(admin::serve(admin_listener, podman_connector.clone()));
+    }
+    let (policy_tx, policy_rx) = watch::channel(policy);
+    let mut filters_handler = filter::FiltersHandler::new(policy_rx);
+
+    // The config file is not used while learning, there is nothing to reload
+    match &learner {
+        Some(learner) => {
+            filters_handler = filters_handler.with_learner(learner.clone());
+        }
+        None => {
+            let reloader = reload::ConfigReloader::new(
+                args.config_path.clone(),
+                args.profile.clone(),
+                config,
+                policy_tx,
+            );
+            let watch_config = args.watch_config;
+            tokio::spawn(async move {
+                if let Err(e) = reloader.run(watch_config).await {
+                    log::error!("Config reload disabled: {:#}", e);
+                }
+            });
+        }
+    }
+
+    let context = ProxyContext {
+        semaphore: semaphore.clone(),
+        podman_connector,
+        audit,
+        clients: Arc::new(client_policies),
+        shutdown: shutdown_rx,
+        closing: closing_rx,
+    };
+    let serving: Vec<_> = listeners
+        .into_iter()
+        .map(|(listener, name, own_filters)| {
+            let filters_handler = own_filters.unwrap_or_else(|| filters_handler.clone());
+            tokio::spawn(serve(listener, name, filters_handler, context.clone()))
+        })
+        .collect();
+
+    proxy::systemd::notify(NotifyState::Ready);
+    proxy::systemd::spawn_watchdog();
+
+    if let Err(e) = shutdown_signal().await {
+        remove_socket_files(&socket_paths).await;
+        return Err(e).context("Failed to wait for the shutdown signals");
+    }
+
+    // Stop accepting, then let the connections end once their requests are answered
+    proxy::systemd::notify(NotifyState::Stopping);
+    shutdown_tx.send_replace(true);
+    for listener in serving {
+        let _ = listener.await;
+    }
+    remove_socket_files(&socket_paths).await;
+
+    let open = MAX_CONCURRENT_CONNECTIONS - semaphore.available_permits();
+    log::info!(
+        "Shutting down, waiting up to {} for {} connection(s)",
+        humantime::format_duration(args.shutdown_timeout),
+        open
+    );
+    let drained = tokio::time::timeout(
+        args.shutdown_timeout,
+        semaphore.acquire_many(MAX_CONCURRENT_CONNECTIONS as u32),
+    )
+    .await;
+    if drained.is_err() {
+        // e.g. /events streams
+        log::warn!(
+            "Closing {} connection(s) still open",
+            MAX_CONCURRENT_CONNECTIONS - semaphore.available_permits()
+        );
+        closing_tx.send_replace(true);
+
+        let closed = tokio::time::timeout(
+            CLOSE_TIMEOUT,
+            semaphore.acquire_many(MAX_CONCURRENT_CONNECTIONS as u32),
+        )
+        .await;
+        if closed.is_err() {
+            log::warn!(
+                "{} connection(s) not closed cleanly",
+                MAX_CONCURRENT_CONNECTIONS - semaphore.available_permits()
+            );
+        }
+    }
+    context.audit.drain().await;
+
+    if let Some(learner) = learner {
+        learner
+            .write()
+            .context("Failed to write the learned policy")?;
+    }
 
     Ok(())
 }
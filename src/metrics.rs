@@ -0,0 +1,123 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::audit::{AuditRecord, Decision};
+use crate::config::METHODS;
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+    static ref REQUESTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "podman_socket_proxy_requests_total",
+            "Requests by method, decision and deciding rule"
+        ),
+        &["method", "decision", "rule"]
+    ));
+    static ref UPSTREAM_RESPONSES: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "podman_socket_proxy_upstream_responses_total",
+            "Responses of Podman to forwarded requests by status code"
+        ),
+        &["status"]
+    ));
+    static ref REQUEST_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new(
+            "podman_socket_proxy_request_duration_seconds",
+            "Time from receiving a request to the head of its response"
+        ),
+        &["method", "decision"]
+    ));
+    static ref ACTIVE_CONNECTIONS: IntGauge = register(IntGauge::new(
+        "podman_socket_proxy_active_connections",
+        "Client connections being handled"
+    ));
+    static ref MAX_CONNECTIONS: IntGauge = register(IntGauge::new(
+        "podman_socket_proxy_max_connections",
+        "Client connections handled at most at the same time"
+    ));
+    static ref UPSTREAM_CONNECT_FAILURES: IntCounter = register(IntCounter::new(
+        "podman_socket_proxy_upstream_connect_failures_total",
+        "Failed connections to the Podman socket"
+    ));
+}
+
+/// Register a metric to the registry served by the admin listener
+fn register<M: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<M>) -> M {
+    let metric = metric.expect("invalid metric definition");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric registered twice");
+    metric
+}
+
+/// Count an answered request
+pub fn observe_request(record: &AuditRecord) {
+    // Methods come from the clients, other ones share a series
+    let method = match record.method.as_deref() {
+        Some(method) => METHODS
+            .iter()
+            .find(|known| **known == method)
+            .copied()
+            .unwrap_or("other"),
+        None => "none",
+    };
+    let decision = record.decision.label();
+
+    REQUESTS
+        .with_label_values(&[method, decision, record.rule.as_deref().unwrap_or("none")])
+        .inc();
+    REQUEST_DURATION
+        .with_label_values(&[method, decision])
+        .observe(record.duration_ms / 1000.0);
+
+    // Denied requests are answered by the proxy
+    if let (Decision::Allowed | Decision::WouldDeny, Some(status)) =
+        (record.decision, record.status)
+    {
+        UPSTREAM_RESPONSES
+            .with_label_values(&[&status.to_string()])
+            .inc();
+    }
+}
+
+/// Register every metric, so that they are exported before being updated
+pub fn init(max_connections: usize) {
+    lazy_static::initialize(&REQUESTS);
+    lazy_static::initialize(&UPSTREAM_RESPONSES);
+    lazy_static::initialize(&REQUEST_DURATION);
+    lazy_static::initialize(&ACTIVE_CONNECTIONS);
+    lazy_static::initialize(&UPSTREAM_CONNECT_FAILURES);
+    MAX_CONNECTIONS.set(max_connections as i64);
+}
+
+/// Count a client connection until the guard is dropped
+pub fn connection_started() -> ConnectionGuard {
+    ACTIVE_CONNECTIONS.inc();
+    ConnectionGuard
+}
+
+pub struct ConnectionGuard;
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        ACTIVE_CONNECTIONS.dec();
+    }
+}
+
+pub fn upstream_connect_failed() {
+    UPSTREAM_CONNECT_FAILURES.inc();
+}
+
+/// Encode the metrics in the Prometheus text format
+///
+/// Returns the content type and the encoded metrics.
+pub fn encode() -> prometheus::Result<(String, Vec<u8>)> {
+    let mut body = Vec::new();
+    let encoder = TextEncoder::new();
+    encoder.encode(&REGISTRY.gather(), &mut body)?;
+
+    Ok((encoder.format_type().to_string(), body))
+}
@@ -0,0 +1,566 @@
+use lazy_static::lazy_static;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::Regex;
+use thiserror::Error;
+
+use crate::query;
+
+lazy_static! {
+    static ref VERSION_SEGMENT: Regex = Regex::new(r"^v[0-9][0-9A-Za-z.\-]*$").unwrap();
+    static ref PLACEHOLDER_NAME: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+}
+
+/// A single segment holding a container, pod, volume or network ID or name
+const SEGMENT_PATTERN: &str = r"[a-zA-Z0-9][a-zA-Z0-9_.\-]*";
+
+/// One or more segments holding an image reference
+const SEGMENTS_PATTERN: &str = r"[a-zA-Z0-9][a-zA-Z0-9_.:@+/\-]*";
+
+/// Characters encoded again when the normalized path is forwarded
+const PATH_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+#[derive(Error, Debug)]
+pub enum PathError {
+    #[error("request target is not an absolute path")]
+    NotAbsolute(),
+    #[error("invalid percent-encoding")]
+    InvalidEncoding(),
+    #[error("ambiguous character in path")]
+    AmbiguousCharacter(),
+}
+
+/// An API version from a path prefix such as `/v1.41` or `/v4.9.0`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiVersion(Vec<u64>);
+
+impl ApiVersion {
+    /// Parse the numeric components of a version, ignoring any suffix like `-rc1`
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let numeric = version
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()
+            .unwrap_or_default();
+
+        numeric
+            .split('.')
+            .filter(|component| !component.is_empty())
+            .map(|component| component.parse().ok())
+            .collect::<Option<Vec<u64>>>()
+            .filter(|components| !components.is_empty())
+            .map(ApiVersion)
+    }
+
+    /// Compare two versions, missing components being 0
+    pub fn compare(&self, other: &ApiVersion) -> std::cmp::Ordering {
+        let size = self.0.len().max(other.0.len());
+        let component = |version: &ApiVersion, i: usize| version.0.get(i).copied().unwrap_or(0);
+
+        (0..size)
+            .map(|i| component(self, i).cmp(&component(other, i)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    }
+}
+
+impl std::fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let components: Vec<String> = self.0.iter().map(u64::to_string).collect();
+        write!(f, "{}", components.join("."))
+    }
+}
+
+/// A comparison operator of a version requirement
+#[derive(Clone, Copy)]
+enum VersionOperator {
+    Equal,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+/// A range of API versions, e.g. `>=1.40, <1.44`
+///
+/// A version without operator requires an equal version.
+pub struct VersionRequirement(Vec<(VersionOperator, ApiVersion)>);
+
+impl VersionRequirement {
+    pub fn parse(requirement: &str) -> Option<Self> {
+        requirement
+            .split(',')
+            .map(|comparator| {
+                let comparator = comparator.trim();
+                let (operator, version) = [
+                    (">=", VersionOperator::GreaterOrEqual),
+                    ("<=", VersionOperator::LessOrEqual),
+                    (">", VersionOperator::Greater),
+                    ("<", VersionOperator::Less),
+                    ("=", VersionOperator::Equal),
+                ]
+                .into_iter()
+                .find_map(|(prefix, operator)| {
+                    comparator
+                        .strip_prefix(prefix)
+                        .map(|version| (operator, version))
+                })
+                .unwrap_or((VersionOperator::Equal, comparator));
+
+                ApiVersion::parse(version.trim()).map(|version| (operator, version))
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(VersionRequirement)
+    }
+
+    pub fn matches(&self, version: &ApiVersion) -> bool {
+        self.0.iter().all(|(operator, required)| {
+            let ordering = version.compare(required);
+
+            match operator {
+                VersionOperator::Equal => ordering.is_eq(),
+                VersionOperator::Greater => ordering.is_gt(),
+                VersionOperator::GreaterOrEqual => ordering.is_ge(),
+                VersionOperator::Less => ordering.is_lt(),
+                VersionOperator::LessOrEqual => ordering.is_le(),
+            }
+        })
+    }
+}
+
+/// A request path once normalized
+pub struct NormalizedPath {
+    /// The decoded path, version prefix included
+    pub path: String,
+    /// The decoded path without its version prefix
+    pub canonical: String,
+    pub version: Option<ApiVersion>,
+    /// The raw query string
+    pub query: String,
+}
+
+impl NormalizedPath {
+    /// Get the request target to forward, the path being encoded again
+    pub fn target(&self) -> String {
+        let path = utf8_percent_encode(&self.path, PATH_ENCODE_SET).to_string();
+
+        match self.query.is_empty() {
+            true => path,
+            false => format!("{}?{}", path, self.query),
+        }
+    }
+}
+
+/// Compile a route template such as `/containers/{id}/logs` into an anchored regex
+///
+/// `{name}` matches a single segment made of the characters allowed in IDs
+/// and names, `{name:*}` matches one or more segments, e.g. an image reference.
+///
+/// # Errors
+///
+/// The reason is returned for templates not starting with `/`, with unbalanced
+/// braces or with an invalid placeholder.
+pub fn route_to_regex(route: &str) -> Result<String, String> {
+    if !route.starts_with('/') {
+        return Err(format!("route {} does not start with /", route));
+    }
+
+    let mut pattern = String::from("^");
+    let mut rest = route;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("unclosed placeholder in route {}", route)),
+        };
+
+        let literal = &rest[..start];
+        if literal.contains('}') {
+            return Err(format!("unopened placeholder in route {}", route));
+        }
+        pattern.push_str(&regex::escape(literal));
+
+        let (name, kind) = rest[start + 1..end]
+            .split_once(':')
+            .unwrap_or((&rest[start + 1..end], ""));
+        if !PLACEHOLDER_NAME.is_match(name) {
+            return Err(format!(
+                "invalid placeholder {{{}}} in route {}",
+                name, route
+            ));
+        }
+
+        match kind {
+            "" => pattern.push_str(SEGMENT_PATTERN),
+            "*" => pattern.push_str(SEGMENTS_PATTERN),
+            _ => {
+                return Err(format!(
+                    "invalid placeholder type {} in route {}",
+                    kind, route
+                ))
+            }
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    if rest.contains('}') {
+        return Err(format!("unopened placeholder in route {}", route));
+    }
+    pattern.push_str(&regex::escape(rest));
+    pattern.push('$');
+
+    Ok(pattern)
+}
+
+/// Normalize a request target
+///
+/// Repeated slashes are collapsed, dot segments are resolved and percent-encoded
+/// characters are decoded. The API version prefix, if any, is parsed and removed
+/// from the canonical path.
+///
+/// # Errors
+///
+/// Targets which are not absolute paths, use an invalid encoding or contain
+/// encoded slashes, backslashes or control characters are rejected as they
+/// could be understood differently by Podman.
+pub fn normalize(target: &str) -> Result<NormalizedPath, PathError> {
+    let (path, query) = query::split_target(target);
+    if !path.starts_with('/') {
+        return Err(PathError::NotAbsolute());
+    }
+
+    let mut segments: Vec<String> = Vec::new();
+    for segment in path.split('/') {
+        let segment = decode_segment(segment)?;
+
+        match segment.as_str() {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let trailing_slash = path.ends_with('/');
+    let join = |segments: &[String]| {
+        let mut path = format!("/{}", segments.join("/"));
+        if path.len() > 1 && trailing_slash {
+            path.push('/');
+        }
+        path
+    };
+
+    let (canonical, version) = match segments.first() {
+        Some(first) if VERSION_SEGMENT.is_match(first) => {
+            (join(&segments[1..]), ApiVersion::parse(first))
+        }
+        _ => (join(&segments), None),
+    };
+
+    Ok(NormalizedPath {
+        path: join(&segments),
+        canonical,
+        version,
+        query: query.to_string(),
+    })
+}
+
+/// Decode a path segment, rejecting characters that could change its meaning
+fn decode_segment(segment: &str) -> Result<String, PathError> {
+    let bytes = segment.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = match bytes[i] {
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or(PathError::InvalidEncoding())?;
+                i += 3;
+
+                if hex == b'/' || hex == b'%' {
+                    return Err(PathError::AmbiguousCharacter());
+                }
+                hex
+            }
+            byte => {
+                i += 1;
+                byte
+            }
+        };
+
+        if byte == b'\\' || byte.is_ascii_control() {
+            return Err(PathError::AmbiguousCharacter());
+        }
+        decoded.push(byte);
+    }
+
+    String::from_utf8(decoded).map_err(|_| PathError::InvalidEncoding())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str) -> ApiVersion {
+        ApiVersion::parse(version).unwrap()
+    }
+
+    #[test]
+    fn normalize_dot_segments() {
+        let path = normalize("//v1.41/containers/./abc/../json/.").unwrap();
+        assert_eq!(path.path, "/v1.41/containers/json");
+        assert_eq!(path.canonical, "/containers/json");
+
+        let path = normalize("/containers/json/").unwrap();
+        assert_eq!(path.canonical, "/containers/json/");
+    }
+
+    #[test]
+    fn normalize_above_root() {
+        for target in [
+            "/../../containers/json",
+            "/%2e%2e/%2E%2E/containers/json",
+            "/v1.41/../../../containers/json",
+            "/images/%2e%2e/%2e%2e/%2e%2e/containers/json",
+        ] {
+            let path = normalize(target).unwrap();
+            assert_eq!(path.canonical, "/containers/json", "{}", target);
+            assert_eq!(path.version, None, "{}", target);
+        }
+
+        assert_eq!(normalize("/..").unwrap().canonical, "/");
+    }
+
+    #[test]
+    fn normalize_encoded_dot_segments() {
+        let path = normalize("/v1.41/containers/abc/%2e%2E/%2e/xyz/json").unwrap();
+        assert_eq!(path.canonical, "/containers/xyz/json");
+
+        // Only whole segments are dot segments
+        let path = normalize("/images/%2e%2ename/json").unwrap();
+        assert_eq!(path.canonical, "/images/..name/json");
+    }
+
+    #[test]
+    fn normalize_not_absolute() {
+        for target in ["containers/json", "http://podman/containers/json", "*", ""] {
+            assert!(
+                matches!(normalize(target), Err(PathError::NotAbsolute())),
+                "{}",
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn decode_ambiguous_characters() {
+        for segment in [
+            "a%2Fb",
+            "a%2fb",
+            "%25",
+            "%252e%252e",
+            "a%5Cb",
+            "a%5cb",
+            "a\\b",
+            "%00",
+            "%0A",
+            "%0d",
+            "%7F",
+            "a\tb",
+        ] {
+            assert!(
+                matches!(
+                    decode_segment(segment),
+                    Err(PathError::AmbiguousCharacter())
+                ),
+                "{}",
+                segment
+            );
+        }
+
+        assert!(matches!(
+            normalize("/containers/a%2F..%2F..%2Fimages/json"),
+            Err(PathError::AmbiguousCharacter())
+        ));
+    }
+
+    #[test]
+    fn decode_invalid_encoding() {
+        for segment in ["%", "%2", "a%g0", "%zz", "%C3%28", "%FF", "%E2%82"] {
+            assert!(
+                matches!(decode_segment(segment), Err(PathError::InvalidEncoding())),
+                "{}",
+                segment
+            );
+        }
+    }
+
+    #[test]
+    fn decode_valid_segments() {
+        assert_eq!(decode_segment("my%20name").unwrap(), "my name");
+        assert_eq!(decode_segment("caf%C3%A9").unwrap(), "café");
+        assert_eq!(decode_segment("nginx%3Alatest").unwrap(), "nginx:latest");
+        assert_eq!(decode_segment("%2e%2E").unwrap(), "..");
+    }
+
+    #[test]
+    fn strip_version_prefix() {
+        let path = normalize("/v1.41/containers/json?all=1").unwrap();
+        assert_eq!(path.path, "/v1.41/containers/json");
+        assert_eq!(path.canonical, "/containers/json");
+        assert_eq!(path.version, Some(version("1.41")));
+        assert_eq!(path.query, "all=1");
+
+        let path = normalize("/v4.9.0/libpod/info").unwrap();
+        assert_eq!(path.canonical, "/libpod/info");
+        assert_eq!(path.version, Some(version("4.9.0")));
+
+        let path = normalize("/v5.0.0-rc1/libpod/info").unwrap();
+        assert_eq!(path.version, Some(version("5.0.0")));
+
+        let path = normalize("/v1.41").unwrap();
+        assert_eq!(path.canonical, "/");
+
+        // Only the first segment is a version prefix
+        for target in ["/version", "/containers/v1.41/json", "/vabc/info"] {
+            let path = normalize(target).unwrap();
+            assert_eq!(path.canonical, target);
+            assert_eq!(path.version, None, "{}", target);
+        }
+    }
+
+    #[test]
+    fn target_encodes_again() {
+        let target = |target: &str| normalize(target).unwrap().target();
+
+        assert_eq!(
+            target("/v1.41//containers/./abc/../json?all=1"),
+            "/v1.41/containers/json?all=1"
+        );
+        assert_eq!(
+            target("/containers/my%20name/json"),
+            "/containers/my%20name/json"
+        );
+        assert_eq!(target("/images/caf%C3%A9/json"), "/images/caf%C3%A9/json");
+        assert_eq!(target("/images/a%3Fb/json"), "/images/a%3Fb/json");
+        assert_eq!(
+            target("/images/a%23b%7Bc%7D/json"),
+            "/images/a%23b%7Bc%7D/json"
+        );
+        assert_eq!(
+            target("/images/nginx%3Alatest/json"),
+            "/images/nginx:latest/json"
+        );
+        assert_eq!(
+            target("/%2e%2e/containers/json?filters=%7B%7D"),
+            "/containers/json?filters=%7B%7D"
+        );
+    }
+
+    #[test]
+    fn version_requirement() {
+        let requirement = VersionRequirement::parse(">=1.40, <1.44").unwrap();
+        assert!(requirement.matches(&version("1.40")));
+        assert!(requirement.matches(&version("1.43.9")));
+        assert!(!requirement.matches(&version("1.39")));
+        assert!(!requirement.matches(&version("1.44")));
+        assert!(!requirement.matches(&version("1.44.0")));
+
+        let requirement = VersionRequirement::parse("1.41").unwrap();
+        assert!(requirement.matches(&version("1.41.0")));
+        assert!(!requirement.matches(&version("1.41.1")));
+
+        let requirement = VersionRequirement::parse("=4").unwrap();
+        assert!(requirement.matches(&version("v4.0.0")));
+
+        let requirement = VersionRequirement::parse(">4, <=5.1").unwrap();
+        assert!(requirement.matches(&version("4.0.1")));
+        assert!(requirement.matches(&version("5.1")));
+        assert!(!requirement.matches(&version("4")));
+        assert!(!requirement.matches(&version("5.1.1")));
+    }
+
+    #[test]
+    fn version_requirement_invalid() {
+        for requirement in ["", "abc", ">=x", ">=1.40,", "~1.40"] {
+            assert!(
+                VersionRequirement::parse(requirement).is_none(),
+                "{}",
+                requirement
+            );
+        }
+    }
+
+    fn route(route: &str) -> Regex {
+        Regex::new(&route_to_regex(route).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn route_escapes_literals() {
+        let version = route("/version");
+        assert!(version.is_match("/version"));
+        assert!(!version.is_match("/versions"));
+        assert!(!version.is_match("/v4.0.0/version"));
+
+        let dotted = route("/libpod/info.json");
+        assert!(dotted.is_match("/libpod/info.json"));
+        assert!(!dotted.is_match("/libpod/infoxjson"));
+
+        assert!(route("/a+b(c)").is_match("/a+b(c)"));
+        assert!(!route("/a+b(c)").is_match("/aab"));
+    }
+
+    #[test]
+    fn route_placeholders() {
+        let logs = route("/containers/{id}/logs");
+        assert!(logs.is_match("/containers/traefik/logs"));
+        assert!(logs.is_match("/containers/0123abc.x-y_z/logs"));
+        assert!(!logs.is_match("/containers/a/b/logs"));
+        assert!(!logs.is_match("/containers//logs"));
+        assert!(!logs.is_match("/containers/-x/logs"));
+
+        let image = route("/images/{name:*}/json");
+        assert!(image.is_match("/images/quay.io/podman/stable:latest/json"));
+        assert!(image.is_match("/images/alpine@sha256:abc/json"));
+        assert!(!image.is_match("/images//json"));
+
+        assert!(route("/containers/{id}").is_match("/containers/x"));
+        assert!(!route("/containers/{id}").is_match("/containers/x/kill"));
+    }
+
+    #[test]
+    fn route_malformed() {
+        for template in [
+            "containers/{id}",
+            "/containers/{id",
+            "/containers/id}",
+            "/containers/{id}}",
+            "/containers/{}",
+            "/containers/{1d}",
+            "/containers/{id:+}",
+            "/containers/{a{b}}",
+        ] {
+            assert!(route_to_regex(template).is_err(), "{}", template);
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use regex::{Regex, RegexSet};
+
+use crate::config::{
+    Config, ConfigParsingError, ContainerCreate, Filters, HTTPRegexParseError, Mode, Rule,
+    RuleAction, METHODS,
+};
+use crate::images::ImagePolicy;
+use crate::path::{self, NormalizedPath, VersionRequirement};
+use crate::query;
+
+/// A constraint on a query parameter, with its value pattern compiled
+struct QueryConstraint {
+    name: String,
+    required: bool,
+    forbidden: bool,
+    values: Vec<String>,
+    regex: Option<Regex>,
+}
+
+impl QueryConstraint {
+    fn is_satisfied(&self, params: &[(String, String)]) -> bool {
+        let mut values = query::values(params, &self.name).peekable();
+
+        if values.peek().is_none() {
+            return !self.required;
+        }
+
+        !self.forbidden
+            && values.all(|value| {
+                (self.values.is_empty() || self.values.iter().any(|v| v == value))
+                    && self
+                        .regex
+                        .as_ref()
+                        .is_none_or(|regex| regex.is_match(value))
+            })
+    }
+}
+
+/// The path a rule pattern is matched against
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Subject {
+    /// The normalized path, version prefix included
+    Path,
+    /// The normalized path without its version prefix
+    Canonical,
+}
+
+/// A rule stripped from its path pattern, the pattern lives in a `RegexSet` of the method
+pub struct CompiledRule {
+    pub name: String,
+    pub action: RuleAction,
+    pub mode: Mode,
+    api_version: Option<VersionRequirement>,
+    query: Vec<QueryConstraint>,
+}
+
+impl CompiledRule {
+    fn compile(method: &str, rule: Rule) -> Result<(Self, Subject, String), ConfigParsingError> {
+        let rule_error = |reason: String| {
+            ConfigParsingError::RuleError(method.to_string(), rule.name.clone(), reason)
+        };
+
+        let (subject, pattern) = match (&rule.regex, &rule.canonical, &rule.route) {
+            (Some(regex), None, None) => (Subject::Path, regex.clone()),
+            (None, Some(canonical), None) => (Subject::Canonical, canonical.clone()),
+            (None, None, Some(route)) => (
+                Subject::Canonical,
+                path::route_to_regex(route).map_err(rule_error)?,
+            ),
+            _ => {
+                return Err(rule_error(String::from(
+                    "exactly one of regex, canonical or route is required",
+                )))
+            }
+        };
+
+        // Compiling each pattern on its own gives the faulty rule in the error
+        if let Err(e) = Regex::new(&pattern) {
+            return Err(HTTPRegexParseError::new(method.to_string(), rule.name, e).into());
+        }
+
+        let api_version = rule
+            .api_version
+            .as_deref()
+            .map(|requirement| {
+                VersionRequirement::parse(requirement)
+                    .ok_or_else(|| rule_error(format!("invalid api_version {}", requirement)))
+            })
+            .transpose()?;
+
+        let query = rule
+            .query
+            .into_iter()
+            .map(|(param, query_rule)| {
+                let regex = query_rule
+                    .regex
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .map_err(|e| {
+                        HTTPRegexParseError::new(
+                            method.to_string(),
+                            format!("{} query {}", rule.name, param),
+                            e,
+                        )
+                    })?;
+
+                Ok(QueryConstraint {
+                    name: param,
+                    required: query_rule.required,
+                    forbidden: query_rule.forbidden,
+                    values: query_rule.values,
+                    regex,
+                })
+            })
+            .collect::<Result<_, HTTPRegexParseError>>()?;
+
+        let compiled = CompiledRule {
+            name: rule.name,
+            action: rule.action,
+            mode: rule.mode,
+            api_version,
+            query,
+        };
+
+        Ok((compiled, subject, pattern))
+    }
+
+    fn is_satisfied(&self, path: &NormalizedPath, params: &[(String, String)]) -> bool {
+        let version_matches = match (&self.api_version, &path.version) {
+            (None, _) => true,
+            (Some(requirement), Some(version)) => requirement.matches(version),
+            (Some(_), None) => false,
+        };
+
+        version_matches
+            && self
+                .query
+                .iter()
+                .all(|constraint| constraint.is_satisfied(params))
+    }
+}
+
+/// Check that a rule compiles, without building the policy of its method
+pub fn validate_rule(method: &str, rule: &Rule) -> Result<(), ConfigParsingError> {
+    CompiledRule::compile(method, rule.clone()).map(|_| ())
+}
+
+/// Patterns of a method matched against the same path, with the index of their rule
+struct SubjectSet {
+    set: RegexSet,
+    rules: Vec<usize>,
+}
+
+impl SubjectSet {
+    fn compile(
+        method: &str,
+        patterns: &[(Subject, String)],
+        subject: Subject,
+    ) -> Result<Self, HTTPRegexParseError> {
+        let (rules, patterns): (Vec<usize>, Vec<&String>) = patterns
+            .iter()
+            .enumerate()
+            .filter(|(_, (s, _))| *s == subject)
+            .map(|(index, (_, pattern))| (index, pattern))
+            .unzip();
+
+        let set = RegexSet::new(patterns)
+            .map_err(|e| HTTPRegexParseError::new(method.to_string(), String::from("*"), e))?;
+
+        Ok(SubjectSet { set, rules })
+    }
+
+    fn matches<'a>(&'a self, path: &str) -> impl Iterator<Item = usize> + 'a {
+        self.set
+            .matches(path)
+            .into_iter()
+            .map(|index| self.rules[index])
+    }
+}
+
+/// The ordered rules of an HTTP method, matched at once with a `RegexSet` per subject
+pub struct MethodPolicy {
+    path_set: SubjectSet,
+    canonical_set: SubjectSet,
+    rules: Vec<CompiledRule>,
+}
+
+impl MethodPolicy {
+    fn compile(filters: &Filters, method: &str) -> Result<Self, ConfigParsingError> {
+        let (rules, patterns): (Vec<CompiledRule>, Vec<(Subject, String)>) = filters
+            .method_rules(method)
+            .into_iter()
+            .map(|rule| CompiledRule::compile(method, rule))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|(rule, subject, pattern)| (rule, (subject, pattern)))
+            .unzip();
+
+        Ok(MethodPolicy {
+            path_set: SubjectSet::compile(method, &patterns, Subject::Path)?,
+            canonical_set: SubjectSet::compile(method, &patterns, Subject::Canonical)?,
+            rules,
+        })
+    }
+
+    /// Get the first rule matching the path and query parameters, if any
+    ///
+    /// Rules in audit mode are skipped unless `include_audit` is set.
+    pub fn first_match(
+        &self,
+        path: &NormalizedPath,
+        params: &[(String, String)],
+        include_audit: bool,
+    ) -> Option<&CompiledRule> {
+        let mut matches: Vec<usize> = self
+            .path_set
+            .matches(&path.path)
+            .chain(self.canonical_set.matches(&path.canonical))
+            .collect();
+        matches.sort_unstable();
+
+        matches
+            .into_iter()
+            .map(|index| &self.rules[index])
+            .filter(|rule| include_audit || rule.mode == Mode::Enforce)
+            .find(|rule| rule.is_satisfied(path, params))
+    }
+}
+
+/// The compiled filters, built once when the configuration is loaded
+pub struct Policy {
+    pub mode: Mode,
+    /// Whether some rules are in audit mode
+    audit_rules: bool,
+    pub container_create: ContainerCreate,
+    pub images: Option<ImagePolicy>,
+    get: MethodPolicy,
+    head: MethodPolicy,
+    post: MethodPolicy,
+    put: MethodPolicy,
+    patch: MethodPolicy,
+    delete: MethodPolicy,
+}
+
+impl Policy {
+    /// Compile the rules of every method of the config
+    ///
+    /// # Errors
+    ///
+    /// If an invalid pattern is found in the filters, an error is returned.
+    /// An error is also returned if the pattern is valid, but would
+    /// produce a regex that is bigger than the size limit configured in the regex library.
+    pub fn compile(config: &Config) -> Result<Self, ConfigParsingError> {
+        let filters = &config.filters;
+        let [get, head, post, put, patch, delete] = METHODS;
+        let audit_rules = METHODS.into_iter().any(|method| {
+            filters
+                .method_rules(method)
+                .iter()
+                .any(|rule| rule.mode == Mode::Audit)
+        });
+
+        Ok(Policy {
+            mode: config.mode,
+            audit_rules,
+            container_create: config.container_create.clone(),
+            images: config
+                .images
+                .as_ref()
+                .map(ImagePolicy::compile)
+                .transpose()
+                .map_err(|(index, e)| ConfigParsingError::ImagePatternError(index, e))?,
+            get: MethodPolicy::compile(filters, get)?,
+            head: MethodPolicy::compile(filters, head)?,
+            post: MethodPolicy::compile(filters, post)?,
+            put: MethodPolicy::compile(filters, put)?,
+            patch: MethodPolicy::compile(filters, patch)?,
+            delete: MethodPolicy::compile(filters, delete)?,
+        })
+    }
+
+    /// Check if some decisions are only audited, by the whole policy or some of its rules
+    pub fn audits(&self) -> bool {
+        self.mode == Mode::Audit || self.audit_rules
+    }
+
+    /// Get the policy of an HTTP method, `None` if the method is not supported
+    pub fn method(&self, method: &str) -> Option<&MethodPolicy> {
+        match method {
+            "GET" => Some(&self.get),
+            "HEAD" => Some(&self.head),
+            "POST" => Some(&self.post),
+            "PUT" => Some(&self.put),
+            "PATCH" => Some(&self.patch),
+            "DELETE" => Some(&self.delete),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constraint(
+        required: bool,
+        forbidden: bool,
+        values: &[&str],
+        regex: Option<&str>,
+    ) -> QueryConstraint {
+        QueryConstraint {
+            name: String::from("force"),
+            required,
+            forbidden,
+            values: values.iter().map(|value| value.to_string()).collect(),
+            regex: regex.map(|regex| Regex::new(regex).unwrap()),
+        }
+    }
+
+    fn params(query: &str) -> Vec<(String, String)> {
+        query::parse_query(query)
+    }
+
+    #[test]
+    fn required_parameters() {
+        let required = constraint(true, false, &[], None);
+
+        assert!(required.is_satisfied(&params("force=true")));
+        assert!(required.is_satisfied(&params("force=")));
+        assert!(!required.is_satisfied(&params("")));
+        assert!(!required.is_satisfied(&params("forced=true")));
+        assert!(constraint(false, false, &[], None).is_satisfied(&params("")));
+    }
+
+    #[test]
+    fn forbidden_parameters() {
+        let forbidden = constraint(false, true, &[], None);
+
+        assert!(forbidden.is_satisfied(&params("all=true")));
+        assert!(!forbidden.is_satisfied(&params("force=false")));
+        assert!(!forbidden.is_satisfied(&params("all=true&force")));
+    }
+
+    #[test]
+    fn parameter_values() {
+        let values = constraint(false, false, &["false", "0"], None);
+
+        assert!(values.is_satisfied(&params("")));
+        assert!(values.is_satisfied(&params("force=false")));
+        assert!(values.is_satisfied(&params("force=0&force=false")));
+        assert!(!values.is_satisfied(&params("force=true")));
+        assert!(!values.is_satisfied(&params("force=False")));
+
+        let regex = constraint(false, false, &[], Some("^[0-9]+$"));
+
+        assert!(regex.is_satisfied(&params("force=10")));
+        assert!(!regex.is_satisfied(&params("force=10s")));
+        assert!(!regex.is_satisfied(&params("force=%31x")));
+    }
+
+    #[test]
+    fn repeated_parameters_must_all_match() {
+        let values = constraint(true, false, &["false"], None);
+
+        assert!(!values.is_satisfied(&params("force=false&force=true")));
+        assert!(!values.is_satisfied(&params("force=true&force=false")));
+    }
+
+    #[test]
+    fn parameter_names_ignore_case() {
+        let values = constraint(false, false, &["false"], None);
+
+        assert!(!values.is_satisfied(&params("Force=true")));
+        assert!(!values.is_satisfied(&params("force=false&FORCE=true")));
+        assert!(!constraint(false, true, &[], None).is_satisfied(&params("fOrCe=1")));
+        assert!(constraint(true, false, &[], None).is_satisfied(&params("FORCE=1")));
+        // Names are decoded before being compared
+        assert!(!values.is_satisfied(&params("f%6Frce=true")));
+    }
+}
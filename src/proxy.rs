@@ -1,14 +1,31 @@
 pub mod client;
+pub mod systemd;
 pub mod tcp;
+pub mod tls;
 pub mod unix;
+pub mod upstream;
 
+use serde::Serialize;
 use tokio::io::{self, AsyncWriteExt};
 use tokio::io::{AsyncReadExt, BufReader};
 use tokio::net;
+use tokio::sync::watch;
+use tokio_rustls::server::TlsStream;
+
+use tls::TlsAcceptor;
+
+/// Wait until the proxy shuts down
+pub async fn shutting_down(shutdown: &mut watch::Receiver<bool>) {
+    if shutdown.wait_for(|shutdown| *shutdown).await.is_err() {
+        // Without a sender, the proxy never shuts down
+        std::future::pending::<()>().await;
+    }
+}
 
 pub enum ProxyListener {
     Inet(net::TcpListener),
     Unix(net::UnixListener),
+    Tls(net::TcpListener, TlsAcceptor),
 }
 
 impl From<net::TcpListener> for ProxyListener {
@@ -24,15 +41,38 @@ impl From<net::UnixListener> for ProxyListener {
 }
 
 impl ProxyListener {
-    pub async fn accept(&self) -> io::Result<ProxyStream> {
+    pub async fn accept(&self) -> io::Result<Accepted> {
         match self {
             ProxyListener::Inet(listener) => {
                 let (stream, _) = listener.accept().await?;
-                Ok(ProxyStream::Inet(stream))
+                Ok(Accepted::Ready(ProxyStream::Inet(stream)))
             }
             ProxyListener::Unix(listener) => {
                 let (stream, _) = listener.accept().await?;
-                Ok(ProxyStream::Unix(stream))
+                Ok(Accepted::Ready(ProxyStream::Unix(stream)))
+            }
+            ProxyListener::Tls(listener, acceptor) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Accepted::Handshake(stream, acceptor.clone()))
+            }
+        }
+    }
+}
+
+/// An accepted connection, whose TLS handshake is done apart from the accept loop
+pub enum Accepted {
+    Ready(ProxyStream),
+    Handshake(net::TcpStream, TlsAcceptor),
+}
+
+impl Accepted {
+    /// Get the stream of the connection, once the TLS handshake is done
+    pub async fn establish(self) -> io::Result<ProxyStream> {
+        match self {
+            Accepted::Ready(stream) => Ok(stream),
+            Accepted::Handshake(stream, acceptor) => {
+                let stream = acceptor.accept(stream).await?;
+                Ok(ProxyStream::Tls(Box::new(stream)))
             }
         }
     }
@@ -41,6 +81,7 @@ impl ProxyListener {
 pub enum ProxyStream {
     Inet(net::TcpStream),
     Unix(net::UnixStream),
+    Tls(Box<TlsStream<net::TcpStream>>),
 }
 
 impl From<net::TcpStream> for ProxyStream {
@@ -55,7 +96,81 @@ impl From<net::UnixStream> for ProxyStream {
     }
 }
 
+/// The identity of a client, as known from its connection
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ClientIdentity {
+    Inet {
+        address: Option<String>,
+    },
+    /// The credentials of the peer process, when the platform provides them
+    Unix {
+        uid: Option<u32>,
+        gid: Option<u32>,
+        pid: Option<i32>,
+        /// Read from `/proc/<pid>/exe`, if the proxy may
+        exe: Option<String>,
+    },
+}
+
+impl std::fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let unknown = || String::from("?");
+
+        match self {
+            ClientIdentity::Inet { address } => {
+                write!(f, "{}", address.clone().unwrap_or_else(unknown))
+            }
+            ClientIdentity::Unix { uid, gid, pid, exe } => write!(
+                f,
+                "uid={} gid={} pid={} exe={}",
+                uid.map_or_else(unknown, |uid| uid.to_string()),
+                gid.map_or_else(unknown, |gid| gid.to_string()),
+                pid.map_or_else(unknown, |pid| pid.to_string()),
+                exe.clone().unwrap_or_else(unknown),
+            ),
+        }
+    }
+}
+
+/// Get the path of the executable of a process
+///
+/// The process may have exited, or its pid been reused, since it connected.
+fn executable_path(pid: i32) -> Option<String> {
+    std::fs::read_link(format!("/proc/{}/exe", pid))
+        .ok()
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
 impl ProxyStream {
+    /// Get the identity of the client connected to the stream
+    pub fn identity(&self) -> ClientIdentity {
+        match self {
+            ProxyStream::Inet(stream) => ClientIdentity::Inet {
+                address: stream.peer_addr().ok().map(|address| address.to_string()),
+            },
+            ProxyStream::Tls(stream) => ClientIdentity::Inet {
+                address: stream
+                    .get_ref()
+                    .0
+                    .peer_addr()
+                    .ok()
+                    .map(|address| address.to_string()),
+            },
+            ProxyStream::Unix(stream) => {
+                let credentials = stream.peer_cred().ok();
+                let pid = credentials.and_then(|c| c.pid());
+
+                ClientIdentity::Unix {
+                    uid: credentials.map(|c| c.uid()),
+                    gid: credentials.map(|c| c.gid()),
+                    pid,
+                    exe: pid.and_then(executable_path),
+                }
+            }
+        }
+    }
+
     pub fn split(self) -> (ProxyBufferedRead, ProxyWriteHalf) {
         match self {
             ProxyStream::Inet(stream) => {
@@ -72,6 +187,13 @@ impl ProxyStream {
                     ProxyWriteHalf::Unix(write),
                 )
             }
+            ProxyStream::Tls(stream) => {
+                let (read, write) = io::split(*stream);
+                (
+                    ProxyBufferedRead::Tls(BufReader::new(read)),
+                    ProxyWriteHalf::Tls(write),
+                )
+            }
         }
     }
 }
@@ -79,6 +201,7 @@ impl ProxyStream {
 pub enum ProxyBufferedRead {
     Inet(tokio::io::BufReader<net::tcp::OwnedReadHalf>),
     Unix(tokio::io::BufReader<net::unix::OwnedReadHalf>),
+    Tls(tokio::io::BufReader<io::ReadHalf<TlsStream<net::TcpStream>>>),
 }
 
 impl ProxyBufferedRead {
@@ -86,6 +209,7 @@ impl ProxyBufferedRead {
         match self {
             ProxyBufferedRead::Inet(read) => read.read_buf(buf).await,
             ProxyBufferedRead::Unix(read) => read.read_buf(buf).await,
+            ProxyBufferedRead::Tls(read) => read.read_buf(buf).await,
         }
     }
 }
@@ -93,6 +217,7 @@ impl ProxyBufferedRead {
 pub enum ProxyWriteHalf {
     Inet(net::tcp::OwnedWriteHalf),
     Unix(net::unix::OwnedWriteHalf),
+    Tls(io::WriteHalf<TlsStream<net::TcpStream>>),
 }
 
 impl ProxyWriteHalf {
@@ -100,6 +225,16 @@ impl ProxyWriteHalf {
         match self {
             ProxyWriteHalf::Inet(write) => write.write_all(buf).await,
             ProxyWriteHalf::Unix(write) => write.write_all(buf).await,
+            ProxyWriteHalf::Tls(write) => write.write_all(buf).await,
+        }
+    }
+
+    /// Close the writing side, with a TLS close_notify
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        match self {
+            ProxyWriteHalf::Inet(write) => write.shutdown().await,
+            ProxyWriteHalf::Unix(write) => write.shutdown().await,
+            ProxyWriteHalf::Tls(write) => write.shutdown().await,
         }
     }
 }
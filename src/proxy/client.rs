@@ -1,96 +1,873 @@
 use log;
-use tokio::{io::AsyncWriteExt, net::unix::OwnedWriteHalf, sync::mpsc::Sender};
+use std::time::{Duration, Instant};
+use tokio::{
+    io::AsyncWriteExt,
+    net::unix::OwnedWriteHalf,
+    sync::{
+        mpsc::{Sender, UnboundedSender},
+        watch,
+    },
+    time::timeout,
+};
 
 use crate::{
+    audit::{AuditLog, Decision, PendingRequest},
     errors::ReadCompleteError,
     filter::{FilterResult, FiltersHandler},
-    responses::{close_response, ClientReponse, BAD_REQUEST, FORBIDDEN, NOT_ALLOWED},
+    path,
+    responses::{close_response, forbidden, ClientReponse, BAD_REQUEST, FORBIDDEN, NOT_ALLOWED},
 };
 
-use super::ProxyBufferedRead;
+use super::{ClientIdentity, ProxyBufferedRead, ProxyStream};
 
 const MAX_REQUEST_SIZE: usize = 10 * 1024 * 1024; // 10MB
+const REJECT_READ_TIMEOUT: Duration = Duration::from_secs(1);
 
-async fn read_request(proxy_reader: &mut ProxyBufferedRead) -> Result<Vec<u8>, ReadCompleteError> {
-    let mut buffer: Vec<u8> = Vec::with_capacity(1024 * 1024);
-    let mut is_valid = false;
+/// The head of an HTTP request read from a client
+struct RequestHead {
+    /// The head as received
+    raw: Vec<u8>,
+    framing: Framing,
+}
+
+/// How the end of a request body is found
+#[derive(Clone, Copy)]
+enum Framing {
+    Length(usize),
+    Chunked,
+}
+
+/// Get the framing of a request body from its headers
+///
+/// # Errors
+///
+/// Requests with both a Content-Length and a Transfer-Encoding header, repeated
+/// framing headers or any other transfer coding than `chunked` are rejected to
+/// prevent request smuggling.
+fn body_framing(headers: &[httparse::Header]) -> Result<Framing, ReadCompleteError> {
+    let find = |name: &str| -> Vec<&[u8]> {
+        headers
+            .iter()
+            .filter(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value)
+            .collect()
+    };
+
+    let content_length = find("content-length");
+    let transfer_encoding = find("transfer-encoding");
 
-    let mut temp_buffer: Vec<u8> = Vec::with_capacity(1024 * 1024);
-    while !is_valid {
-        let size = proxy_reader.read(&mut temp_buffer).await?;
-        if size == 0 {
-            return Err(ReadCompleteError::NoData());
+    match (content_length.as_slice(), transfer_encoding.as_slice()) {
+        ([], []) => Ok(Framing::Length(0)),
+        ([value], []) => {
+            let value = std::str::from_utf8(value)
+                .map_err(|_| ReadCompleteError::InvalidContentLength())?
+                .trim();
+
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ReadCompleteError::InvalidContentLength());
+            }
+
+            value
+                .parse()
+                .map(Framing::Length)
+                .map_err(|_| ReadCompleteError::InvalidContentLength())
+        }
+        ([], [value]) if value.trim_ascii().eq_ignore_ascii_case(b"chunked") => {
+            Ok(Framing::Chunked)
         }
+        ([], _) => Err(ReadCompleteError::UnsupportedTransferEncoding()),
+        _ => Err(ReadCompleteError::ConflictingFraming()),
+    }
+}
+
+/// Decode a chunked body
+///
+/// Returns `None` while the body is incomplete, otherwise the size of the body
+/// on the wire, trailers included, and the decoded body.
+fn decode_chunked(buffer: &[u8]) -> Result<Option<(usize, Vec<u8>)>, ReadCompleteError> {
+    let mut body = Vec::new();
+    let mut position = 0;
 
-        if buffer.len() + size > MAX_REQUEST_SIZE {
+    loop {
+        let (line_size, chunk_size) = match httparse::parse_chunk_size(&buffer[position..]) {
+            Ok(httparse::Status::Complete(chunk)) => chunk,
+            Ok(httparse::Status::Partial) => return Ok(None),
+            Err(_) => return Err(ReadCompleteError::InvalidChunk()),
+        };
+        position += line_size;
+
+        if chunk_size == 0 {
+            break;
+        }
+
+        let chunk_size =
+            usize::try_from(chunk_size).map_err(|_| ReadCompleteError::ExceededMaxSize())?;
+        if chunk_size > MAX_REQUEST_SIZE {
             return Err(ReadCompleteError::ExceededMaxSize());
         }
 
-        buffer.append(&mut temp_buffer);
+        if buffer.len() < position + chunk_size + 2 {
+            return Ok(None);
+        }
+
+        body.extend_from_slice(&buffer[position..position + chunk_size]);
+        position += chunk_size;
+
+        if &buffer[position..position + 2] != b"\r\n" {
+            return Err(ReadCompleteError::InvalidChunk());
+        }
+        position += 2;
+    }
+
+    // Skip the trailers, up to the empty line ending the body
+    loop {
+        let line_end = match buffer[position..].windows(2).position(|w| w == b"\r\n") {
+            Some(line_end) => line_end,
+            None => return Ok(None),
+        };
+        position += line_end + 2;
+
+        if line_end == 0 {
+            return Ok(Some((position, body)));
+        }
+    }
+}
+
+/// Rewrite the request line with the normalized target, so that Podman routes the path the filters matched
+fn normalize_request_line(req: &httparse::Request, raw: &[u8]) -> Vec<u8> {
+    let (method, target, version) = match (req.method, req.path, req.version) {
+        (Some(method), Some(target), Some(version)) => (method, target, version),
+        _ => return raw.to_vec(),
+    };
 
-        let status = httparse::Request::new(&mut [httparse::EMPTY_HEADER; 64]).parse(&buffer)?;
+    let normalized = match path::normalize(target) {
+        Ok(path) => path.target(),
+        Err(_) => return raw.to_vec(),
+    };
 
-        is_valid = status.is_complete();
+    if normalized == target {
+        return raw.to_vec();
     }
 
-    Ok(buffer)
+    let line_end = match raw.iter().position(|&b| b == b'\n') {
+        Some(end) if end > 0 && raw[end - 1] == b'\r' => end - 1,
+        Some(end) => end,
+        None => return raw.to_vec(),
+    };
+
+    let mut forwarded = format!("{} {} HTTP/1.{}", method, normalized, version).into_bytes();
+    forwarded.extend_from_slice(&raw[line_end..]);
+    forwarded
+}
+
+/// Read more bytes from the client, `buffer` holding at most `MAX_REQUEST_SIZE` bytes
+async fn read_more(
+    proxy_reader: &mut ProxyBufferedRead,
+    buffer: &mut Vec<u8>,
+) -> Result<(), ReadCompleteError> {
+    buffer.reserve(64 * 1024);
+
+    let size = proxy_reader.read(buffer).await?;
+    if size == 0 {
+        return Err(ReadCompleteError::NoData());
+    }
+
+    if buffer.len() > MAX_REQUEST_SIZE {
+        return Err(ReadCompleteError::ExceededMaxSize());
+    }
+
+    Ok(())
+}
+
+/// Read the head of a request from the client
+///
+/// The head is removed from `buffer`, which keeps the bytes received after it.
+async fn read_head(
+    proxy_reader: &mut ProxyBufferedRead,
+    buffer: &mut Vec<u8>,
+) -> Result<RequestHead, ReadCompleteError> {
+    loop {
+        if !buffer.is_empty() {
+            let mut headers = [httparse::EMPTY_HEADER; 64];
+            let mut req = httparse::Request::new(&mut headers);
+
+            if let httparse::Status::Complete(header_size) = req.parse(buffer)? {
+                let framing = body_framing(req.headers)?;
+                let raw = buffer.drain(..header_size).collect();
+
+                return Ok(RequestHead { raw, framing });
+            }
+        }
+
+        read_more(proxy_reader, buffer).await?;
+    }
+}
+
+/// Read the whole body of a request, for the filters to inspect it
+///
+/// Returns the body as received and without its transfer encoding. Bytes
+/// received after the end of the request are kept in `buffer` for the next request.
+async fn read_body(
+    proxy_reader: &mut ProxyBufferedRead,
+    buffer: &mut Vec<u8>,
+    head: &RequestHead,
+) -> Result<(Vec<u8>, Vec<u8>), ReadCompleteError> {
+    loop {
+        let complete = match head.framing {
+            Framing::Length(length) => {
+                if head.raw.len() + length > MAX_REQUEST_SIZE {
+                    return Err(ReadCompleteError::ExceededMaxSize());
+                }
+
+                (buffer.len() >= length).then(|| (length, buffer[..length].to_vec()))
+            }
+            Framing::Chunked => decode_chunked(buffer)?,
+        };
+
+        if let Some((size, body)) = complete {
+            let raw = buffer.drain(..size).collect();
+            return Ok((raw, body));
+        }
+
+        read_more(proxy_reader, buffer).await?;
+    }
+}
+
+/// Forward the next `size` bytes received from the client to Podman
+async fn forward_exact(
+    proxy_reader: &mut ProxyBufferedRead,
+    buffer: &mut Vec<u8>,
+    podman_write: &mut OwnedWriteHalf,
+    mut size: u64,
+) -> anyhow::Result<()> {
+    while size > 0 {
+        if buffer.is_empty() {
+            read_more(proxy_reader, buffer).await?;
+        }
+
+        let count = buffer
+            .len()
+            .min(usize::try_from(size).unwrap_or(usize::MAX));
+        podman_write.write_all(&buffer[..count]).await?;
+        buffer.drain(..count);
+        size -= count as u64;
+    }
+
+    Ok(())
+}
+
+/// Forward the body of a request to Podman as it is received, without buffering it
+///
+/// The end of the body is found from its framing, bytes received after it are
+/// kept in `buffer` for the next request.
+async fn forward_body(
+    proxy_reader: &mut ProxyBufferedRead,
+    buffer: &mut Vec<u8>,
+    podman_write: &mut OwnedWriteHalf,
+    framing: Framing,
+) -> anyhow::Result<()> {
+    if let Framing::Length(length) = framing {
+        return forward_exact(proxy_reader, buffer, podman_write, length as u64).await;
+    }
+
+    loop {
+        let (line_size, chunk_size) = loop {
+            match httparse::parse_chunk_size(buffer) {
+                Ok(httparse::Status::Complete(chunk)) => break chunk,
+                Ok(httparse::Status::Partial) => read_more(proxy_reader, buffer).await?,
+                Err(_) => return Err(ReadCompleteError::InvalidChunk().into()),
+            }
+        };
+        forward_exact(proxy_reader, buffer, podman_write, line_size as u64).await?;
+
+        if chunk_size == 0 {
+            break;
+        }
+        forward_exact(proxy_reader, buffer, podman_write, chunk_size).await?;
+
+        while buffer.len() < 2 {
+            read_more(proxy_reader, buffer).await?;
+        }
+        if &buffer[..2] != b"\r\n" {
+            return Err(ReadCompleteError::InvalidChunk().into());
+        }
+        forward_exact(proxy_reader, buffer, podman_write, 2).await?;
+    }
+
+    // The trailers, up to the empty line ending the body
+    loop {
+        let line_end = loop {
+            match buffer.windows(2).position(|w| w == b"\r\n") {
+                Some(line_end) => break line_end,
+                None => read_more(proxy_reader, buffer).await?,
+            }
+        };
+        forward_exact(proxy_reader, buffer, podman_write, line_end as u64 + 2).await?;
+
+        if line_end == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Read and drop what the client sends for a while, so that it gets the
+/// response of a request whose body is not read instead of a reset connection
+async fn discard_body(proxy_reader: &mut ProxyBufferedRead, buffer: &mut Vec<u8>) {
+    let discard = async {
+        loop {
+            buffer.clear();
+            match proxy_reader.read(buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+    };
+
+    let _ = timeout(REJECT_READ_TIMEOUT, discard).await;
+}
+
+/// Answer a request which cannot be read with a 400, recording it
+async fn reject_bad_request(
+    writer_channel: &Sender<ClientReponse>,
+    audit: &AuditLog,
+    client: &ClientIdentity,
+    request_bytes: usize,
+    e: ReadCompleteError,
+) -> anyhow::Result<()> {
+    writer_channel.send(close_response(BAD_REQUEST)).await?;
+    log::debug!("Bad request from {}: {}", client, e);
+
+    let pending = PendingRequest {
+        client: client.clone(),
+        method: None,
+        path: None,
+        rule: None,
+        decision: Decision::Denied,
+        reason: None,
+        request_bytes: Some(request_bytes as u64),
+        started: Instant::now(),
+    };
+    audit.record(pending.complete(Some(400), Some(BAD_REQUEST.len() as u64)));
+
+    Ok(())
+}
+
+/// Answer a client which cannot be served, with the error response
+///
+/// The start of the request is read first, so that the client gets the
+/// response instead of a reset connection.
+pub async fn reject_client(stream: ProxyStream, response: &str) -> std::io::Result<()> {
+    let (mut proxy_reader, mut proxy_writer) = stream.split();
+
+    let mut buffer = Vec::with_capacity(64 * 1024);
+    let _ = timeout(REJECT_READ_TIMEOUT, proxy_reader.read(&mut buffer)).await;
+
+    proxy_writer.write(response.as_bytes()).await
 }
 
 // Warning - Limitation
 // No support for HTTP/2 Portocol Switching, too risky to implement
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_client(
     mut proxy_reader: ProxyBufferedRead,
     mut podman_write: OwnedWriteHalf,
     writer_channel: Sender<ClientReponse>,
     filters_handler: FiltersHandler,
+    client: ClientIdentity,
+    forwarded: UnboundedSender<PendingRequest>,
+    audit: AuditLog,
+    mut shutdown: watch::Receiver<bool>,
 ) -> anyhow::Result<()> {
+    let mut buffer: Vec<u8> = Vec::with_capacity(1024 * 1024);
+
     loop {
         let filters_handler = filters_handler.clone();
 
-        let request_buffer = match read_request(&mut proxy_reader).await {
-            Ok(buffer) => buffer,
+        let read = tokio::select! {
+            read = read_head(&mut proxy_reader, &mut buffer) => read,
+            _ = super::shutting_down(&mut shutdown) => {
+                // Closing the connection to Podman would cancel the requests in progress
+                log::debug!("Shutting down, waiting for the responses to {}", client);
+                forwarded.closed().await;
+                break;
+            }
+        };
+
+        let head = match read {
+            Ok(head) => head,
             Err(ReadCompleteError::NoData()) => break,
             Err(ReadCompleteError::ReadError(_)) => break,
-            Err(_) => {
-                writer_channel.send(close_response(BAD_REQUEST)).await?;
-                log::debug!("Bad request");
-                break;
+            Err(e) => {
+                let request_bytes = buffer.len();
+                return reject_bad_request(&writer_channel, &audit, &client, request_bytes, e)
+                    .await;
             }
         };
+        let started = Instant::now();
 
-        log::debug!(
-            "Received request: {:?}",
-            String::from_utf8_lossy(&request_buffer)
-        );
+        log::debug!("Received request: {:?}", String::from_utf8_lossy(&head.raw));
         let mut headers = [httparse::EMPTY_HEADER; 64];
         let mut req = httparse::Request::new(&mut headers);
+        req.parse(&head.raw)?;
 
-        let result = req.parse(&request_buffer)?;
-        if result.is_partial() {
-            writer_channel.send(close_response(BAD_REQUEST)).await?;
-            log::debug!("Bad request");
-            break;
+        // Only the bodies inspected by the filters are read before deciding,
+        // the others are forwarded as they are received
+        let mut verdict = filters_handler.is_action_allowed(&req, req.headers);
+        let mut body = None;
+        if verdict.result.is_allowed() && FiltersHandler::inspects_body(&req) {
+            let (raw, decoded) = match read_body(&mut proxy_reader, &mut buffer, &head).await {
+                Ok(read) => read,
+                Err(ReadCompleteError::NoData()) => break,
+                Err(ReadCompleteError::ReadError(_)) => break,
+                Err(e) => {
+                    let request_bytes = head.raw.len() + buffer.len();
+                    return reject_bad_request(&writer_channel, &audit, &client, request_bytes, e)
+                        .await;
+                }
+            };
+
+            verdict = verdict.and(filters_handler.is_body_allowed(&req, &decoded));
+            body = Some(raw);
         }
 
-        match filters_handler.is_action_allowed(&req, &req.headers) {
+        let path = req.path.map(|target| match path::normalize(target) {
+            Ok(path) => path.target(),
+            Err(_) => target.to_string(),
+        });
+
+        // Requests decided differently by the decisions in audit mode are logged
+        let (decision, rule, reason) = match (verdict.result.is_allowed(), verdict.audited) {
+            (true, None) => (Decision::Allowed, verdict.rule, None),
+            (false, None) => (Decision::Denied, verdict.rule, verdict.result.reason()),
+            (allowed, Some((audited, audited_rule))) => {
+                log::warn!(
+                    "Audit: {} {} from {} would be {}{}",
+                    req.method.unwrap_or_default(),
+                    path.as_deref().unwrap_or_default(),
+                    client,
+                    audited,
+                    audited_rule
+                        .as_ref()
+                        .map(|rule| format!(", rule {}", rule))
+                        .unwrap_or_default()
+                );
+
+                match allowed {
+                    true => (Decision::WouldDeny, audited_rule, audited.reason()),
+                    false => (Decision::WouldAllow, audited_rule, None),
+                }
+            }
+        };
+
+        // The size of streamed chunked bodies is only known once forwarded
+        let request_bytes = match (&body, head.framing) {
+            (Some(body), _) => Some((head.raw.len() + body.len()) as u64),
+            (None, Framing::Length(length)) => Some((head.raw.len() + length) as u64),
+            (None, Framing::Chunked) => None,
+        };
+
+        let pending = PendingRequest {
+            client: client.clone(),
+            method: req.method.map(String::from),
+            path,
+            rule,
+            decision,
+            reason,
+            request_bytes,
+            started,
+        };
+
+        // Denied requests are answered with Connection: close
+        let response = match verdict.result {
             FilterResult::Allowed => {
-                podman_write.write_all(&request_buffer).await?;
+                let forwarded_request = normalize_request_line(&req, &head.raw);
+
+                // Sent first, so that it is known when Podman answers
+                let _ = forwarded.send(pending);
+                podman_write.write_all(&forwarded_request).await?;
+
+                let sent = match body {
+                    Some(body) => podman_write.write_all(&body).await.map_err(Into::into),
+                    None => {
+                        forward_body(
+                            &mut proxy_reader,
+                            &mut buffer,
+                            &mut podman_write,
+                            head.framing,
+                        )
+                        .await
+                    }
+                };
+                if let Err(e) = sent {
+                    log::debug!(
+                        "Error forwarding the body of a request from {}: {}",
+                        client,
+                        e
+                    );
+                    break;
+                }
+
                 log::debug!("Request sent to Podman");
+                continue;
             }
             FilterResult::MethodNotAllowed => {
-                writer_channel.send(close_response(NOT_ALLOWED)).await?;
                 log::debug!("Method not allowed");
+                (405, NOT_ALLOWED.to_string())
             }
             FilterResult::Forbidden => {
-                writer_channel.send(close_response(FORBIDDEN)).await?;
                 log::debug!("Forbidden");
+                (403, FORBIDDEN.to_string())
             }
             FilterResult::BadRequest => {
-                writer_channel.send(close_response(BAD_REQUEST)).await?;
                 log::debug!("Bad request");
+                (400, BAD_REQUEST.to_string())
+            }
+            FilterResult::Violation(reason) => {
+                log::debug!("Forbidden: {}", reason);
+                (403, forbidden(&reason))
             }
+        };
+
+        let (status, message) = response;
+        writer_channel.send(close_response(&message)).await?;
+        audit.record(pending.complete(Some(status), Some(message.len() as u64)));
+
+        if body.is_none() && !matches!(head.framing, Framing::Length(0)) {
+            discard_body(&mut proxy_reader, &mut buffer).await;
         }
+
+        break;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::UnixStream;
+
+    fn header<'a>(name: &'a str, value: &'a str) -> httparse::Header<'a> {
+        httparse::Header {
+            name,
+            value: value.as_bytes(),
+        }
+    }
+
+    /// A client sending each part in its own write
+    fn client(parts: &[&str]) -> ProxyBufferedRead {
+        let (client, proxy) = UnixStream::pair().expect("failed to create a socket pair");
+        let parts: Vec<Vec<u8>> = parts.iter().map(|part| part.as_bytes().to_vec()).collect();
+
+        tokio::spawn(async move {
+            let mut client = client;
+            for part in parts {
+                client.write_all(&part).await.expect("failed to write");
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        ProxyStream::from(proxy).split().0
+    }
+
+    #[test]
+    fn framing_without_body() {
+        assert!(matches!(body_framing(&[]), Ok(Framing::Length(0))));
+    }
+
+    #[test]
+    fn framing_content_length() {
+        let headers = [header("Content-Length", " 12 ")];
+        assert!(matches!(body_framing(&headers), Ok(Framing::Length(12))));
+
+        for value in ["+12", "-1", "0x10", "12, 12", ""] {
+            let headers = [header("Content-Length", value)];
+            assert!(
+                matches!(
+                    body_framing(&headers),
+                    Err(ReadCompleteError::InvalidContentLength())
+                ),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn framing_duplicate_content_length() {
+        let headers = [header("Content-Length", "5"), header("content-length", "5")];
+        assert!(matches!(
+            body_framing(&headers),
+            Err(ReadCompleteError::ConflictingFraming())
+        ));
+    }
+
+    #[test]
+    fn framing_content_length_and_transfer_encoding() {
+        let headers = [
+            header("Content-Length", "5"),
+            header("Transfer-Encoding", "chunked"),
+        ];
+        assert!(matches!(
+            body_framing(&headers),
+            Err(ReadCompleteError::ConflictingFraming())
+        ));
+    }
+
+    #[test]
+    fn framing_chunked() {
+        let headers = [header("transfer-encoding", " Chunked ")];
+        assert!(matches!(body_framing(&headers), Ok(Framing::Chunked)));
+
+        for value in ["gzip, chunked", "chunked, chunked", "gzip", "chunked;x=1"] {
+            let headers = [header("Transfer-Encoding", value)];
+            assert!(
+                matches!(
+                    body_framing(&headers),
+                    Err(ReadCompleteError::UnsupportedTransferEncoding())
+                ),
+                "{:?}",
+                value
+            );
+        }
+
+        let headers = [
+            header("Transfer-Encoding", "chunked"),
+            header("Transfer-Encoding", "chunked"),
+        ];
+        assert!(matches!(
+            body_framing(&headers),
+            Err(ReadCompleteError::UnsupportedTransferEncoding())
+        ));
+    }
+
+    #[test]
+    fn chunked_body() {
+        let encoded = b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let (size, body) = decode_chunked(encoded).unwrap().unwrap();
+
+        assert_eq!(size, encoded.len());
+        assert_eq!(body, b"hello world");
+    }
+
+    #[test]
+    fn chunked_body_with_extensions() {
+        let encoded = b"5;name=value\r\nhello\r\n0;last\r\n\r\n";
+        let (size, body) = decode_chunked(encoded).unwrap().unwrap();
+
+        assert_eq!(size, encoded.len());
+        assert_eq!(body, b"hello");
+    }
+
+    #[test]
+    fn chunked_body_with_trailers() {
+        let encoded = b"5\r\nhello\r\n0\r\nX-Checksum: 1\r\nX-Other: 2\r\n\r\n";
+        let (size, body) = decode_chunked(encoded).unwrap().unwrap();
+
+        assert_eq!(size, encoded.len());
+        assert_eq!(body, b"hello");
+    }
+
+    #[test]
+    fn chunked_body_incomplete() {
+        for encoded in [
+            &b""[..],
+            b"5",
+            b"5\r\nhel",
+            b"5\r\nhello",
+            b"5\r\nhello\r\n0\r\n",
+            b"5\r\nhello\r\n0\r\nX-Checksum: 1\r\n",
+        ] {
+            assert!(
+                matches!(decode_chunked(encoded), Ok(None)),
+                "{:?}",
+                String::from_utf8_lossy(encoded)
+            );
+        }
+    }
+
+    #[test]
+    fn chunked_body_invalid() {
+        for encoded in [&b"zz\r\nhello\r\n"[..], b"5\r\nhelloX\r\n0\r\n\r\n"] {
+            assert!(
+                matches!(
+                    decode_chunked(encoded),
+                    Err(ReadCompleteError::InvalidChunk())
+                ),
+                "{:?}",
+                String::from_utf8_lossy(encoded)
+            );
+        }
+    }
+
+    #[test]
+    fn chunked_body_followed_by_next_request() {
+        let encoded = b"5\r\nhello\r\n0\r\n\r\nGET /_ping HTTP/1.1\r\n\r\n";
+        let (size, body) = decode_chunked(encoded).unwrap().unwrap();
+
+        assert_eq!(size, b"5\r\nhello\r\n0\r\n\r\n".len());
+        assert_eq!(body, b"hello");
+    }
+
+    /// A connection to Podman, returning what it received once closed
+    fn podman() -> (OwnedWriteHalf, tokio::task::JoinHandle<Vec<u8>>) {
+        let (proxy, podman) = UnixStream::pair().expect("failed to create a socket pair");
+
+        let received = tokio::spawn(async move {
+            let mut podman = podman;
+            let mut received = Vec::new();
+            podman
+                .read_to_end(&mut received)
+                .await
+                .expect("failed to read");
+            received
+        });
+
+        (proxy.into_split().1, received)
+    }
+
+    #[tokio::test]
+    async fn read_body_split_across_reads() {
+        let head =
+            "POST /containers/create HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\n";
+        let mut reader = client(&[&head[..20], &head[20..], "hello", " world"]);
+        let mut buffer = Vec::new();
+
+        let request = read_head(&mut reader, &mut buffer).await.unwrap();
+        assert_eq!(request.raw, head.as_bytes());
+        assert!(matches!(request.framing, Framing::Length(11)));
+
+        let (raw, body) = read_body(&mut reader, &mut buffer, &request).await.unwrap();
+        assert_eq!(raw, b"hello world");
+        assert_eq!(body, b"hello world");
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_chunked_body_split_across_reads() {
+        let head = "POST /containers/create HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let mut reader = client(&[head, "5;x=1\r\nhel", "lo\r\n0\r\nX-Trailer: 1\r\n", "\r\n"]);
+        let mut buffer = Vec::new();
+
+        let request = read_head(&mut reader, &mut buffer).await.unwrap();
+        let (raw, body) = read_body(&mut reader, &mut buffer, &request).await.unwrap();
+
+        assert_eq!(body, b"hello");
+        assert_eq!(raw, b"5;x=1\r\nhello\r\n0\r\nX-Trailer: 1\r\n\r\n");
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_body_too_large() {
+        let head = format!(
+            "POST /containers/create HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_REQUEST_SIZE
+        );
+        let mut reader = client(&[&head]);
+        let mut buffer = Vec::new();
+
+        let request = read_head(&mut reader, &mut buffer).await.unwrap();
+        assert!(matches!(
+            read_body(&mut reader, &mut buffer, &request).await,
+            Err(ReadCompleteError::ExceededMaxSize())
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_pipelined_requests() {
+        let first = "POST /containers/create HTTP/1.1\r\nContent-Length: 2\r\n\r\n";
+        let second = "GET /_ping HTTP/1.1\r\n\r\n";
+        let mut reader = client(&[&format!("{}{{}}{}", first, second)]);
+        let mut buffer = Vec::new();
+
+        let request = read_head(&mut reader, &mut buffer).await.unwrap();
+        assert_eq!(request.raw, first.as_bytes());
+        let (_, body) = read_body(&mut reader, &mut buffer, &request).await.unwrap();
+        assert_eq!(body, b"{}");
+        assert_eq!(buffer, second.as_bytes());
+
+        let request = read_head(&mut reader, &mut buffer).await.unwrap();
+        assert_eq!(request.raw, second.as_bytes());
+        assert!(matches!(request.framing, Framing::Length(0)));
+        assert!(buffer.is_empty());
+
+        assert!(matches!(
+            read_head(&mut reader, &mut buffer).await,
+            Err(ReadCompleteError::NoData())
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_smuggled_request() {
+        let request = "POST /_ping HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /containers/json HTTP/1.1\r\n\r\n";
+        let mut reader = client(&[request]);
+        let mut buffer = Vec::new();
+
+        assert!(matches!(
+            read_head(&mut reader, &mut buffer).await,
+            Err(ReadCompleteError::ConflictingFraming())
+        ));
+    }
+
+    #[tokio::test]
+    async fn forward_large_body() {
+        let body = "x".repeat(MAX_REQUEST_SIZE + 1);
+        let head = format!(
+            "POST /build HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        );
+        let next = "GET /_ping HTTP/1.1\r\n\r\n";
+        let mut reader = client(&[&head, &body[..1000], &body[1000..], next]);
+        let mut buffer = Vec::new();
+        let (mut podman_write, received) = podman();
+
+        let request = read_head(&mut reader, &mut buffer).await.unwrap();
+        forward_body(&mut reader, &mut buffer, &mut podman_write, request.framing)
+            .await
+            .unwrap();
+        drop(podman_write);
+
+        assert_eq!(received.await.unwrap(), body.as_bytes());
+        let request = read_head(&mut reader, &mut buffer).await.unwrap();
+        assert_eq!(request.raw, next.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn forward_chunked_body() {
+        let head = "POST /images/load HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let body = "5;x=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        let next = "GET /_ping HTTP/1.1\r\n\r\n";
+        let mut reader = client(&[
+            head,
+            &body[..3],
+            &body[3..12],
+            &body[12..40],
+            &format!("{}{}", &body[40..], next),
+        ]);
+        let mut buffer = Vec::new();
+        let (mut podman_write, received) = podman();
+
+        let request = read_head(&mut reader, &mut buffer).await.unwrap();
+        forward_body(&mut reader, &mut buffer, &mut podman_write, request.framing)
+            .await
+            .unwrap();
+        drop(podman_write);
+
+        assert_eq!(received.await.unwrap(), body.as_bytes());
+        assert_eq!(buffer, next.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn forward_invalid_chunked_body() {
+        let head = "POST /images/load HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let mut reader = client(&[head, "5\r\nhelloX\r\n0\r\n\r\n"]);
+        let mut buffer = Vec::new();
+        let (mut podman_write, _) = podman();
+
+        let request = read_head(&mut reader, &mut buffer).await.unwrap();
+        let forwarded =
+            forward_body(&mut reader, &mut buffer, &mut podman_write, request.framing).await;
+        assert!(forwarded.is_err());
+    }
+}
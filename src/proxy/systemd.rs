@@ -0,0 +1,73 @@
+use nix::sys::socket::{getsockname, AddressFamily, SockaddrLike, SockaddrStorage};
+use sd_notify::NotifyState;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+use tokio::net::{TcpListener, UnixListener};
+
+use crate::errors::SystemdError;
+
+use super::ProxyListener;
+
+/// Take the listening socket passed by systemd, by its name if given
+///
+/// The name is the `FileDescriptorName=` of the socket unit, its file name by
+/// default. Without a name, systemd must pass a single socket.
+pub fn inherited_listener(name: Option<&str>) -> Result<ProxyListener, SystemdError> {
+    let sockets: Vec<(RawFd, String)> = sd_notify::listen_fds_with_names(true)?.collect();
+
+    let (fd, socket_name) = match (name, sockets.as_slice()) {
+        (_, []) => return Err(SystemdError::NoSocket()),
+        (None, [socket]) => socket.clone(),
+        (None, _) => return Err(SystemdError::SeveralSockets(sockets.len())),
+        (Some(name), _) => sockets
+            .iter()
+            .find(|(_, socket_name)| socket_name == name)
+            .cloned()
+            .ok_or_else(|| SystemdError::SocketNotFound(name.to_string()))?,
+    };
+
+    let family = getsockname::<SockaddrStorage>(fd)?.family();
+    // Safety: systemd passes the descriptors to the process, which owns them
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let listener = match family {
+        Some(AddressFamily::Unix) => {
+            let listener = std::os::unix::net::UnixListener::from(socket);
+            listener.set_nonblocking(true)?;
+            ProxyListener::Unix(UnixListener::from_std(listener)?)
+        }
+        Some(AddressFamily::Inet | AddressFamily::Inet6) => {
+            let listener = std::net::TcpListener::from(socket);
+            listener.set_nonblocking(true)?;
+            ProxyListener::Inet(TcpListener::from_std(listener)?)
+        }
+        _ => return Err(SystemdError::UnsupportedSocket(socket_name)),
+    };
+
+    log::info!("Listening on: systemd socket {}", socket_name);
+    Ok(listener)
+}
+
+/// Notify systemd of the state of the proxy, when started by a `Type=notify` unit
+pub fn notify(state: NotifyState) {
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        log::warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// Ping the systemd watchdog at half its timeout, if it is enabled
+pub fn spawn_watchdog() {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+
+    let period = Duration::from_micros(usec) / 2;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            notify(NotifyState::Watchdog);
+        }
+    });
+}
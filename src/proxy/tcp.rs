@@ -1,10 +1,36 @@
+use std::net::SocketAddr;
 use tokio::net::TcpListener;
 
-use crate::{cli::InetProxyArgs, errors::OpenInetError};
+use crate::{cli::InetProxyArgs, config::Tls, errors::OpenInetError};
 
-pub async fn open_inet_socket(args: &InetProxyArgs) -> Result<TcpListener, OpenInetError> {
+use super::{tls, ProxyListener};
+
+/// Open the TCP socket of the proxy
+///
+/// The TLS options of the arguments, else of the config, apply.
+pub async fn open_inet_socket(
+    args: &InetProxyArgs,
+    tls: &Tls,
+) -> Result<ProxyListener, OpenInetError> {
     let address = format!("{}:{}", args.ip, args.port);
     let listener = TcpListener::bind(address).await?;
 
-    Ok(listener)
+    let tls = Tls {
+        cert: args.tlscert.clone().or_else(|| tls.cert.clone()),
+        key: args.tlskey.clone().or_else(|| tls.key.clone()),
+        ca_cert: args.tlscacert.clone().or_else(|| tls.ca_cert.clone()),
+        verify: args.tlsverify || tls.verify,
+    };
+
+    Ok(tls::wrap_listener(listener.into(), &tls)?)
+}
+
+/// Open a TCP socket on an address such as `127.0.0.1:8787` or `[::1]:8787`
+pub async fn open_inet_address(address: &str, tls: &Tls) -> Result<ProxyListener, OpenInetError> {
+    let address: SocketAddr = address
+        .parse()
+        .map_err(|_| OpenInetError::InvalidAddress(address.to_string()))?;
+    let listener = TcpListener::bind(address).await?;
+
+    Ok(tls::wrap_listener(listener.into(), tls)?)
 }
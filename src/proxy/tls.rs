@@ -0,0 +1,137 @@
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io;
+use tokio::net::TcpStream;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio_rustls::server::TlsStream;
+
+use crate::config::Tls;
+use crate::errors::TlsError;
+
+use super::ProxyListener;
+
+/// Time given to clients to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts TLS connections with the current certificates, reloaded on SIGHUP
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    config: watch::Receiver<Arc<ServerConfig>>,
+}
+
+impl TlsAcceptor {
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+        let acceptor = tokio_rustls::TlsAcceptor::from(self.config.borrow().clone());
+
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))?
+    }
+}
+
+/// Read the certificates of a PEM file
+fn read_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::PemError(path.to_string(), e))?;
+
+    match certificates.is_empty() {
+        true => Err(TlsError::NoCertificate(path.to_string())),
+        false => Ok(certificates),
+    }
+}
+
+/// Build the TLS config from the certificate files
+///
+/// With `verify`, clients must present a certificate signed by one of the CAs
+/// of `ca_cert`, as with `dockerd --tlsverify`.
+fn server_config(tls: &Tls) -> Result<Arc<ServerConfig>, TlsError> {
+    let (cert, key) = match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => (cert, key),
+        _ => return Err(TlsError::MissingKeyPair()),
+    };
+
+    let certificates = read_certificates(cert)?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| TlsError::PemError(key.clone(), e))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match (tls.verify, &tls.ca_cert) {
+        (false, _) => builder.with_no_client_auth(),
+        (true, None) => return Err(TlsError::MissingCaCertificate()),
+        (true, Some(ca_cert)) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_certificates(ca_cert)? {
+                roots
+                    .add(certificate)
+                    .map_err(|e| TlsError::CaCertificateError(ca_cert.clone(), e))?;
+            }
+
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+
+    Ok(Arc::new(builder.with_single_cert(certificates, key)?))
+}
+
+/// Create an acceptor if TLS is enabled, reloading the certificates on SIGHUP
+///
+/// If the certificates cannot be reloaded, the current ones are kept.
+fn acceptor(tls: &Tls) -> Result<Option<TlsAcceptor>, TlsError> {
+    if tls.cert.is_none() && tls.key.is_none() {
+        return match tls.verify {
+            true => Err(TlsError::MissingKeyPair()),
+            false => Ok(None),
+        };
+    }
+    if tls.ca_cert.is_some() && !tls.verify {
+        log::warn!("The TLS CA certificate is only used to verify clients, with verify");
+    }
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    let (config_tx, config_rx) = watch::channel(server_config(tls)?);
+
+    match tls.verify {
+        true => log::info!(
+            "Serving TLS, with client certificates signed by {}",
+            tls.ca_cert.as_deref().unwrap_or_default()
+        ),
+        false => log::info!("Serving TLS, without client certificates"),
+    }
+
+    let tls = tls.clone();
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match server_config(&tls) {
+                Ok(config) => {
+                    config_tx.send_replace(config);
+                    log::info!("TLS certificates reloaded");
+                }
+                Err(e) => log::error!(
+                    "Failed to reload the TLS certificates, keeping the current ones: {:#}",
+                    anyhow::Error::from(e)
+                ),
+            }
+        }
+    });
+
+    Ok(Some(TlsAcceptor { config: config_rx }))
+}
+
+/// Serve TLS on a TCP listener, if enabled
+///
+/// Unix sockets are left as they are, their clients are local.
+pub fn wrap_listener(listener: ProxyListener, tls: &Tls) -> Result<ProxyListener, TlsError> {
+    match listener {
+        ProxyListener::Inet(listener) => match acceptor(tls)? {
+            Some(acceptor) => Ok(ProxyListener::Tls(listener, acceptor)),
+            None => Ok(ProxyListener::Inet(listener)),
+        },
+        listener => Ok(listener),
+    }
+}
@@ -1,16 +1,117 @@
+use nix::sys::stat::{self, Mode};
+use nix::unistd::{self, Gid, Group, Uid, User};
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use tokio::{fs, net::UnixListener};
 
-use crate::{cli::UnixProxyArgs, errors::OpenUnixSocketError};
+use crate::{cli::UnixProxyArgs, config::Socket, errors::OpenUnixSocketError};
 
-pub async fn open_unix_socket(args: &UnixProxyArgs) -> Result<UnixListener, OpenUnixSocketError> {
-    if fs::metadata(&args.socket_path).await.is_ok() {
-        if !&args.replace {
-            return Err(OpenUnixSocketError::SocketExists());
+/// Parse an octal permission mode, e.g. `0660`
+fn parse_mode(mode: &str) -> Result<u32, OpenUnixSocketError> {
+    u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| OpenUnixSocketError::InvalidMode(mode.to_string()))
+}
+
+/// Resolve a user name or uid
+fn resolve_owner(owner: &str) -> Result<Uid, OpenUnixSocketError> {
+    if let Ok(uid) = owner.parse() {
+        return Ok(Uid::from_raw(uid));
+    }
+
+    match User::from_name(owner) {
+        Ok(Some(user)) => Ok(user.uid),
+        _ => Err(OpenUnixSocketError::UnknownOwner(owner.to_string())),
+    }
+}
+
+/// Resolve a group name or gid
+fn resolve_group(group: &str) -> Result<Gid, OpenUnixSocketError> {
+    if let Ok(gid) = group.parse() {
+        return Ok(Gid::from_raw(gid));
+    }
+
+    match Group::from_name(group) {
+        Ok(Some(group)) => Ok(group.gid),
+        _ => Err(OpenUnixSocketError::UnknownGroup(group.to_string())),
+    }
+}
+
+/// Get the temporary path the socket is bound to, next to its final path
+fn temporary_path(socket_path: &Path) -> PathBuf {
+    let name = socket_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    socket_path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+/// Open the Unix socket of the proxy
+///
+/// The mode, owner and group of the arguments, else of the config, are applied
+/// to the socket bound to a temporary path, which is then renamed, so that
+/// clients never see the socket with other permissions.
+pub async fn open_unix_socket(
+    args: &UnixProxyArgs,
+    socket: &Socket,
+) -> Result<UnixListener, OpenUnixSocketError> {
+    if fs::metadata(&args.socket_path).await.is_ok() && !&args.replace {
+        return Err(OpenUnixSocketError::SocketExists());
+    }
+
+    let mode = args
+        .mode
+        .as_ref()
+        .or(socket.mode.as_ref())
+        .map(|mode| parse_mode(mode))
+        .transpose()?;
+    let owner = args
+        .owner
+        .as_ref()
+        .or(socket.owner.as_ref())
+        .map(|owner| resolve_owner(owner))
+        .transpose()?;
+    let group = args
+        .group
+        .as_ref()
+        .or(socket.group.as_ref())
+        .map(|group| resolve_group(group))
+        .transpose()?;
+
+    let socket_path = Path::new(&args.socket_path);
+    let temporary_path = temporary_path(socket_path);
+    if fs::metadata(&temporary_path).await.is_ok() {
+        fs::remove_file(&temporary_path).await?;
+    }
+
+    // Only the proxy can connect until the permissions are applied. The umask
+    // is shared by the threads, the socket is opened before serving clients.
+    let umask = stat::umask(Mode::from_bits_truncate(0o177));
+    let bound = UnixListener::bind(&temporary_path);
+    stat::umask(umask);
+    let protected_socket = bound?;
+
+    // Without a mode, the socket gets the one it has with the usual umask
+    let mode = mode.unwrap_or(0o777 & !umask.bits());
+
+    let applied = async {
+        fs::set_permissions(&temporary_path, Permissions::from_mode(mode)).await?;
+        if owner.is_some() || group.is_some() {
+            unistd::chown(&temporary_path, owner, group)?;
         }
 
-        fs::remove_file(&args.socket_path).await?
+        fs::rename(&temporary_path, socket_path).await?;
+        Ok::<(), OpenUnixSocketError>(())
+    }
+    .await;
+
+    if let Err(e) = applied {
+        let _ = fs::remove_file(&temporary_path).await;
+        return Err(e);
     }
 
-    let protected_socket: UnixListener = UnixListener::bind(&args.socket_path)?;
     Ok(protected_socket)
 }
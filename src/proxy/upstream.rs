@@ -0,0 +1,317 @@
+use std::collections::VecDeque;
+use tokio::io::{AsyncReadExt, BufReader};
+use tokio::net::unix::OwnedReadHalf;
+use tokio::sync::mpsc::{Sender, UnboundedReceiver};
+use tokio::sync::watch;
+
+use crate::audit::{AuditLog, PendingRequest};
+use crate::responses::{request_response, ClientReponse};
+
+/// Maximum size of the head of a response, larger heads are not tracked
+const MAX_HEAD_SIZE: usize = 1024 * 1024; // 1MB
+
+/// A step of a response read from Podman
+pub enum Progress {
+    /// The head of the response was read
+    Head {
+        status: u16,
+        /// The size of the response on the wire, head included, when known
+        /// from its head
+        size: Option<u64>,
+    },
+    /// The body of the response ended
+    End,
+}
+
+enum Chunk {
+    Size(Vec<u8>),
+    Data(u64),
+    DataEnd(u64),
+    Trailer(Vec<u8>),
+}
+
+enum State {
+    Head,
+    Length(u64),
+    Chunked(Chunk),
+    /// The body ends with the connection, e.g. upgraded or unframed streams
+    UntilClose,
+}
+
+/// Finds where the responses of Podman start and end, without buffering their body
+///
+/// Responses are forwarded as they are read, this only follows their framing so
+/// that each one can be matched with the request it answers.
+pub struct ResponseTracker {
+    state: State,
+    head: Vec<u8>,
+    /// For each request awaiting a response, whether it is a HEAD request
+    expected: VecDeque<bool>,
+}
+
+impl ResponseTracker {
+    pub fn new() -> Self {
+        ResponseTracker {
+            state: State::Head,
+            head: Vec::new(),
+            expected: VecDeque::new(),
+        }
+    }
+
+    /// Expect a response for a request forwarded to Podman
+    pub fn expect(&mut self, is_head: bool) {
+        self.expected.push_back(is_head);
+    }
+
+    /// Follow the bytes read from Podman, returning the heads and ends of
+    /// responses they contain
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Progress> {
+        let mut progress = Vec::new();
+        let mut input = data.to_vec();
+        let mut position = 0;
+
+        while position < input.len() {
+            let available = (input.len() - position) as u64;
+
+            match &mut self.state {
+                State::Head => {
+                    self.head.extend_from_slice(&input[position..]);
+                    position = input.len();
+
+                    if let Some((head, rest)) = self.parse_head() {
+                        progress.extend(head);
+                        input = rest;
+                        position = 0;
+                    }
+                }
+                State::Length(remaining) => {
+                    let consumed = available.min(*remaining);
+                    *remaining -= consumed;
+                    position += consumed as usize;
+                }
+                State::Chunked(chunk) => {
+                    let consumed = match chunk {
+                        Chunk::Size(line) | Chunk::Trailer(line) => {
+                            match input[position..].iter().position(|&b| b == b'\n') {
+                                Some(end) => {
+                                    line.extend_from_slice(&input[position..position + end]);
+                                    end as u64 + 1
+                                }
+                                None => {
+                                    line.extend_from_slice(&input[position..]);
+                                    available
+                                }
+                            }
+                        }
+                        Chunk::Data(remaining) | Chunk::DataEnd(remaining) => {
+                            let consumed = available.min(*remaining);
+                            *remaining -= consumed;
+                            consumed
+                        }
+                    };
+                    let line_ended = input[position..position + consumed as usize].ends_with(b"\n");
+                    position += consumed as usize;
+
+                    self.next_chunk_state(line_ended);
+                }
+                State::UntilClose => {
+                    position = input.len();
+                }
+            }
+
+            if self.is_body_complete() {
+                self.expected.pop_front();
+                self.state = State::Head;
+                progress.push(Progress::End);
+            }
+        }
+
+        progress
+    }
+
+    /// Parse the head of a response, returning the bytes following it once complete
+    ///
+    /// The head is returned with them, unless it is an informational one.
+    fn parse_head(&mut self) -> Option<(Option<Progress>, Vec<u8>)> {
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut response = httparse::Response::new(&mut headers);
+
+        let head_size = match response.parse(&self.head) {
+            Ok(httparse::Status::Complete(head_size)) => head_size,
+            Ok(httparse::Status::Partial) if self.head.len() <= MAX_HEAD_SIZE => return None,
+            _ => {
+                // Not a response this tracker understands, forwarded until the connection ends
+                self.head.clear();
+                self.state = State::UntilClose;
+                return None;
+            }
+        };
+
+        let status = response.code.unwrap_or_default();
+        let has_header = |name: &str, value: &str| {
+            response.headers.iter().any(|header| {
+                header.name.eq_ignore_ascii_case(name)
+                    && String::from_utf8_lossy(header.value)
+                        .to_ascii_lowercase()
+                        .contains(value)
+            })
+        };
+        let content_length = response
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case("content-length"))
+            .and_then(|header| std::str::from_utf8(header.value).ok())
+            .and_then(|value| value.trim().parse::<u64>().ok());
+        let is_chunked = has_header("transfer-encoding", "chunked");
+
+        let rest = self.head.split_off(head_size);
+        self.head.clear();
+
+        self.state = match status {
+            101 => State::UntilClose,
+            // Informational responses come before the final response of the request
+            100..=199 => return Some((None, rest)),
+            204 | 304 => State::Length(0),
+            _ if self.expected.front() == Some(&true) => State::Length(0),
+            _ if is_chunked => State::Chunked(Chunk::Size(Vec::new())),
+            _ => match content_length {
+                Some(length) => State::Length(length),
+                None => State::UntilClose,
+            },
+        };
+
+        let size = match self.state {
+            State::Length(length) => Some(head_size as u64 + length),
+            _ => None,
+        };
+
+        Some((Some(Progress::Head { status, size }), rest))
+    }
+
+    fn next_chunk_state(&mut self, line_ended: bool) {
+        let chunk = match &mut self.state {
+            State::Chunked(chunk) => chunk,
+            _ => return,
+        };
+
+        let next = match chunk {
+            Chunk::Size(line) if line_ended => {
+                let size = String::from_utf8_lossy(line);
+                let size = size.split(';').next().unwrap_or_default().trim();
+
+                match u64::from_str_radix(size, 16) {
+                    Ok(0) => Chunk::Trailer(Vec::new()),
+                    Ok(size) => Chunk::Data(size),
+                    Err(_) => {
+                        self.state = State::UntilClose;
+                        return;
+                    }
+                }
+            }
+            Chunk::Data(0) => Chunk::DataEnd(2),
+            Chunk::DataEnd(0) => Chunk::Size(Vec::new()),
+            Chunk::Trailer(line) if line_ended => {
+                if line.iter().all(|&b| b == b'\r') {
+                    self.state = State::Length(0);
+                    return;
+                }
+                Chunk::Trailer(Vec::new())
+            }
+            _ => return,
+        };
+
+        *chunk = next;
+    }
+
+    fn is_body_complete(&self) -> bool {
+        matches!(self.state, State::Length(0))
+    }
+}
+
+/// Forward the responses of Podman to a client, recording each one in the audit log
+///
+/// Requests are recorded once the head of their response is read, so that
+/// streams are recorded while they last. `pending` receives the requests forwarded to Podman, before they are sent.
+/// On shutdown, the responses in progress are forwarded until their end.
+pub async fn forward_responses(
+    podman_read: OwnedReadHalf,
+    writer_channel: Sender<ClientReponse>,
+    mut pending: UnboundedReceiver<PendingRequest>,
+    audit: AuditLog,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut podman_buffer_reader = BufReader::new(podman_read);
+    let mut tracker = ResponseTracker::new();
+    let mut requests: VecDeque<PendingRequest> = VecDeque::new();
+    // Whether the body of a recorded response is being forwarded
+    let mut answering = false;
+    let mut draining = false;
+
+    let mut response_buffer: Vec<u8> = Vec::with_capacity(1024 * 1024);
+    loop {
+        while let Ok(request) = pending.try_recv() {
+            tracker.expect(request.is_head());
+            requests.push_back(request);
+        }
+
+        // When shutting down, the connection ends once every request is answered
+        if draining && requests.is_empty() && !answering {
+            break;
+        }
+
+        let read = tokio::select! {
+            read = podman_buffer_reader.read_buf(&mut response_buffer) => read,
+            _ = super::shutting_down(&mut shutdown), if !draining => {
+                draining = true;
+                continue;
+            }
+        };
+
+        let size = match read {
+            Ok(size) => size,
+            Err(e) => {
+                log::error!("Error reading from the Podman socket: {}", e);
+                break;
+            }
+        };
+
+        if size == 0 {
+            break;
+        }
+
+        while let Ok(request) = pending.try_recv() {
+            tracker.expect(request.is_head());
+            requests.push_back(request);
+        }
+
+        for progress in tracker.feed(&response_buffer) {
+            match progress {
+                Progress::Head { status, size } => {
+                    if let Some(request) = requests.pop_front() {
+                        audit.record(request.complete(Some(status), size));
+                    }
+                    answering = true;
+                }
+                Progress::End => answering = false,
+            }
+        }
+
+        if let Err(e) = writer_channel
+            .send(request_response(response_buffer.clone()))
+            .await
+        {
+            log::error!("Error sending response to a client: {}", e);
+            break;
+        }
+
+        response_buffer.clear();
+    }
+
+    // Requests left were not answered
+    while let Ok(request) = pending.try_recv() {
+        requests.push_back(request);
+    }
+    for request in requests {
+        audit.record(request.complete(None, None));
+    }
+}
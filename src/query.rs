@@ -0,0 +1,41 @@
+use percent_encoding::percent_decode_str;
+
+/// Split a request target into its path and its raw query string
+pub fn split_target(target: &str) -> (&str, &str) {
+    target.split_once('?').unwrap_or((target, ""))
+}
+
+/// Decode a query string into its parameters, in order of appearance
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+/// Get every value of a parameter
+///
+/// Names are compared case-insensitively, as the Podman API decodes them.
+pub fn values<'a>(params: &'a [(String, String)], name: &'a str) -> impl Iterator<Item = &'a str> {
+    params
+        .iter()
+        .filter(move |(param, _)| param.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Check if a boolean parameter is set to true
+///
+/// Any true value counts, in the forms accepted by Go's `strconv.ParseBool`.
+pub fn is_true(params: &[(String, String)], name: &str) -> bool {
+    values(params, name).any(|value| matches!(value, "1" | "t" | "T" | "true" | "TRUE" | "True"))
+}
+
+fn decode(component: &str) -> String {
+    percent_decode_str(&component.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
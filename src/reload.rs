@@ -0,0 +1,182 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch};
+
+use crate::config::{self, Config};
+use crate::filter::FiltersHandler;
+use crate::policy::Policy;
+
+/// Delay letting editors finish writing the config file before it is read
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Reloads the config and swaps the policy used for new requests
+pub struct ConfigReloader {
+    config_path: String,
+    profile: Option<String>,
+    config: Config,
+    policy: watch::Sender<Arc<Policy>>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        config_path: String,
+        profile: Option<String>,
+        config: Config,
+        policy: watch::Sender<Arc<Policy>>,
+    ) -> Self {
+        ConfigReloader {
+            config_path,
+            profile,
+            config,
+            policy,
+        }
+    }
+
+    /// Reload the config on SIGHUP and, if `watch_file` is set, when the file changes
+    pub async fn run(mut self, watch_file: bool) -> anyhow::Result<()> {
+        let mut hangup = signal(SignalKind::hangup()).context("Failed to handle SIGHUP")?;
+
+        let (changes_tx, mut changes_rx) = mpsc::unbounded_channel();
+        let _watcher = match watch_file {
+            true => Some(
+                watch_config_file(&self.config_path, changes_tx)
+                    .with_context(|| format!("Failed to watch {}", &self.config_path))?,
+            ),
+            false => None,
+        };
+
+        loop {
+            tokio::select! {
+                Some(_) = hangup.recv() => {
+                    log::info!("Received SIGHUP, reloading {}", &self.config_path);
+                }
+                Some(_) = changes_rx.recv() => {
+                    tokio::time::sleep(WATCH_DEBOUNCE).await;
+                    while changes_rx.try_recv().is_ok() {}
+
+                    log::info!("{} changed, reloading", &self.config_path);
+                }
+                else => break,
+            }
+
+            self.reload();
+        }
+
+        Ok(())
+    }
+
+    /// Load and compile the config, keeping the current policy on failure
+    fn reload(&mut self) {
+        let loaded = config::get_config(&self.config_path, self.profile.as_deref())
+            .map_err(anyhow::Error::from)
+            .and_then(|config| {
+                let policy = config::compile_policy(&config)?;
+                Ok((config, policy))
+            });
+
+        match loaded {
+            Ok((config, policy)) => {
+                log::info!(
+                    "Reloaded {}: {}",
+                    &self.config_path,
+                    config::diff_summary(&self.config, &config)
+                );
+
+                self.policy.send_replace(policy);
+                self.config = config;
+            }
+            Err(e) => {
+                log::error!(
+                    "Failed to reload {}, keeping the current policy: {:#}",
+                    &self.config_path,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Load the filters of a listener or client with its own policy
+///
+/// Returns `None` without a config file nor a profile, the policy of the main
+/// config file applies then. The config file, if any, is reloaded like the main one.
+pub fn load_filters(
+    config_path: Option<&str>,
+    profile: Option<&str>,
+    watch_config: bool,
+) -> anyhow::Result<Option<FiltersHandler>> {
+    let config =
+        config::get_own_config(config_path, profile).with_context(|| match config_path {
+            Some(config_path) => format!("Failed to parse config file at {}", config_path),
+            None => String::from("Failed to load the profile"),
+        })?;
+    let config = match config {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+
+    let policy = config::compile_policy(&config).context("Failed to compile filters")?;
+    let (policy_tx, policy_rx) = watch::channel(policy);
+
+    // Bundled profiles do not change
+    if let Some(config_path) = config_path {
+        let reloader = ConfigReloader::new(
+            config_path.to_string(),
+            profile.map(String::from),
+            config,
+            policy_tx,
+        );
+        tokio::spawn(async move {
+            if let Err(e) = reloader.run(watch_config).await {
+                log::error!("Config reload disabled: {:#}", e);
+            }
+        });
+    }
+
+    Ok(Some(FiltersHandler::new(policy_rx)))
+}
+
+/// Watch the directory of the config file, so that it is still watched when
+/// editors replace it
+fn watch_config_file(
+    config_path: &str,
+    changes: mpsc::UnboundedSender<()>,
+) -> notify::Result<notify::RecommendedWatcher> {
+    let path = Path::new(config_path);
+    let file_name = path.file_name().map(|name| name.to_os_string());
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                log::error!("Error watching the config file: {}", e);
+                return;
+            }
+        };
+
+        let is_change = matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        );
+        let is_config = event
+            .paths
+            .iter()
+            .any(|path| path.file_name() == file_name.as_deref());
+
+        if is_change && is_config {
+            let _ = changes.send(());
+        }
+    })?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+
+    Ok(watcher)
+}
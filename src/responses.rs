@@ -2,6 +2,33 @@ pub const BAD_REQUEST: &str = "HTTP/1.1 400 Bad Request\r\nContent-Type: text/pl
 pub const NOT_ALLOWED: &str = "HTTP/1.1 405 Method Not Allowed\r\nContent-Type: text/plain; charset=utf-8\r\nConnection: close\r\n\r\n405 method not allowed";
 pub const FORBIDDEN: &str = "HTTP/1.1 403 Forbidden\r\nContent-Type: text/plain; charset=utf-8\r\nConnection: close\r\n\r\nblocked by proxy";
 
+/// Build a 403 response explaining why the request was blocked
+pub fn forbidden(reason: &str) -> String {
+    let body = format!("blocked by proxy: {}", reason);
+
+    format!(
+        "HTTP/1.1 403 Forbidden\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+/// Build a 503 response with a JSON error, like the errors of the Podman API
+pub fn service_unavailable(cause: &str) -> String {
+    let body = serde_json::json!({
+        "cause": cause,
+        "message": "the Podman API is unavailable",
+        "response": 503,
+    })
+    .to_string();
+
+    format!(
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
 pub struct ClientReponse {
     pub buffer: Vec<u8>,
     pub close: bool,